@@ -1,161 +1,647 @@
-mod tafl;
-use tafl::{board::Board, tmove::TMove};
-use tafl::negamax::Negamax;
+use simpletafl::tafl::{board::Board, tmove::TMove};
+use simpletafl::tafl::negamax::Negamax;
+use simpletafl::tafl::search::{spawn_search, DepthResult, SearchLimits};
+use simpletafl::tafl::clock::{Clock, TimeBudget};
+use simpletafl::tafl::game::{Game, GameResult};
+use simpletafl::tafl::mcts::{Mcts, Playout};
+use simpletafl::tafl::perft::divide;
+use simpletafl::tafl::player::{play_match, HumanPlayer, MctsPlayer, NegamaxPlayer, Player, Report};
+use simpletafl::tafl::pns::{Outcome, ProofSearch, INFINITE};
+use simpletafl::tafl::proof::ProofTree;
+use simpletafl::tafl::retro::unmoves;
+use simpletafl::tafl::score::describe;
+use simpletafl::tafl::solver::{Solver, Value};
+use simpletafl::tafl::index::Signature;
+use simpletafl::tafl::tablebase::Tablebase;
+use futures::executor::block_on;
 use text_io::read;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 use std::str::FromStr;
-use std::time::{Instant};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use argparse::{ArgumentParser, Store, StoreTrue};
 
 
 
-fn search_in_time(negamax: &mut Negamax, board: &Board, start_depth: i32, depth: i32, step: usize, t: f32, pvs: bool) -> (i32, Option<TMove>) {
+fn print_depth_result(negamax: &Negamax, result: &DepthResult, player: u8, elapsed: f32, verbose: bool) {
+    let d = result.depth;
+    let elapsed_d = result.elapsed;
+    let log_len = (negamax.map.len() as f32).log10();
+    let partial = if result.complete { "" } else { "*" };
+    print!("{d}{partial}\t{elapsed_d:.2}s\t{elapsed:.2}s\t{}\t{log_len:.1}", describe(result.score, player));
+    match result.tmove {
+        Some(tmove) => { print!("\t{}\t", tmove) }
+        None => { print!("\t") }
+    }
+    let stats = &result.stats;
+    let nodes = stats.nodes;
+    let knps = stats.nodes_per_second(elapsed_d) / 1000.0;
+    let tt_hits = stats.tt_hit_rate() * 100.0;
+    let tt_stores = stats.tt_store_rate() * 100.0;
+    let first_cutoffs = stats.first_move_cutoff_rate() * 100.0;
+    let branching = match result.branching_factor {
+        Some(branching) => format!("{branching:.2}"),
+        None => "-".to_string()
+    };
+    let seldepth = stats.max_selective_depth;
+    let researches = stats.aspiration_researches;
+    let total = negamax.stats.nodes;
+    let pv = result.pv.iter().map(|tmove| tmove.to_string()).collect::<Vec<String>>().join(" ");
+    println!("{nodes:.1e}\t{knps:.0}\t{tt_hits:.0}%\t{tt_stores:.0}%\t{first_cutoffs:.0}%\t{branching}\t{seldepth}\t{researches}\t{total:.1e}\t{pv}");
+    if verbose {
+        println!("{}", stats);
+    }
+}
+
+fn print_lines(result: &DepthResult, player: u8, elapsed: f32) {
+    let d = result.depth;
+    let partial = if result.complete { "" } else { "*" };
+    for (rank, (score, tmove, pv)) in result.lines.iter().enumerate() {
+        let pv = pv.iter().map(|tmove| tmove.to_string()).collect::<Vec<String>>().join(" ");
+        println!("{d}{partial}\t{elapsed:.2}s\t{}\t{}\t{tmove}\t{pv}", rank + 1, describe(*score, player));
+    }
+}
+
+fn search(negamax: &mut Negamax, board: &Board, limits: &SearchLimits, pvs: bool, verbose: bool) -> Option<DepthResult> {
     let t0 = Instant::now();
-    let mut last_result = negamax.solve(board, start_depth, pvs);
-    println!("Depth\tTime\tTotal\tPts\tLogLen\tMove\tNorm\tTran\tZerW\tPVS-\tTotl");
-    for d in (start_depth+1..=depth).step_by(step) {
-        let t1 = Instant::now();
-        let negamax_result = negamax.solve(board, d, pvs);
-        let elapsed_d = t1.elapsed().as_secs_f32();
-        let elapsed = t0.elapsed().as_secs_f32();
-        let log_len = (negamax.map.len() as f32).log10();
-        print!("{d}\t{elapsed_d:.2}s\t{elapsed:.2}s\t{}\t{log_len:.1}", negamax_result.0);
-        match negamax_result {
-            (_, Some(tmove)) => { print!("\t{}\t", tmove.to_string()) }
-            (_, None) => { print!("\t") }
-        }
-        let normal = negamax.normal_calls;
-        let transpo = negamax.transpo_calls;
-        let zerow = negamax.zero_window_calls;
-        let pvs_fail = negamax.pvs_failed_calls;
-        let total = normal + transpo + zerow + pvs_fail;
-        println!("{normal:.1e}\t{transpo:.1e}\t{zerow}\t{pvs_fail}\t{total:.1e}");
-        if elapsed < t/2.0 {
-            last_result = negamax_result;
-        } else if elapsed <= t {
-            return negamax_result;
-        } else {
-            return last_result;
+    match limits.multipv > 1 {
+        true => println!("{MULTIPV_HEADER}"),
+        false => println!("{HEADER}")
+    }
+    let player = board.get_player();
+    let handle = spawn_search(std::mem::take(negamax), *board, limits.clone(), pvs, move |negamax, result| {
+        match result.lines.is_empty() {
+            true => print_depth_result(negamax, result, player, t0.elapsed().as_secs_f32(), verbose),
+            false => print_lines(result, player, t0.elapsed().as_secs_f32())
         }
+    });
+    let outcome = block_on(handle);
+    *negamax = outcome.negamax;
+    outcome.result
+}
+
+const HEADER: &str = "Depth\tTime\tTotal\tPts\tLogLen\tMove\tNodes\tkN/s\tTTHit\tTTSt\tCut1st\tEBF\tSelD\tAsp\tTotl\tPV";
+const MULTIPV_HEADER: &str = "Depth\tTotal\tRank\tPts\tMove\tPV";
+const MCTS_HEADER: &str = "Depth\tTime\tTotal\tPts\tTree\tMove\tNodes\tkN/s\tPV";
+
+fn print_mcts_result(mcts: &Mcts, result: &DepthResult, player: u8, elapsed: f32) {
+    let knps = result.stats.nodes_per_second(result.elapsed) / 1000.0;
+    let tmove = result.tmove.map(|tmove| tmove.to_string()).unwrap_or_default();
+    let pv = result.pv.iter().map(|tmove| tmove.to_string()).collect::<Vec<String>>().join(" ");
+    println!("{}\t{:.2}s\t{elapsed:.2}s\t{}\t{}\t{tmove}\t{:.1e}\t{knps:.0}\t{pv}", result.depth, result.elapsed,
+        describe(result.score, player), mcts.len(), result.stats.nodes);
+}
+
+fn search_mcts(mcts: &mut Mcts, board: &Board, limits: &SearchLimits) -> Option<DepthResult> {
+    let t0 = Instant::now();
+    println!("{MCTS_HEADER}");
+    let player = board.get_player();
+    mcts.clear_stop();
+    mcts.search(board, limits, |mcts, result| {
+        print_mcts_result(mcts, result, player, t0.elapsed().as_secs_f32());
+    })
+}
+
+/// Prints the iterations of an engine playing a game, with a header whenever it starts on a new
+/// position. Two searches of a player in a row never share a position, its own move changed it.
+fn report_iterations<E: 'static>(header: &'static str, print: impl Fn(&E, &DepthResult, u8, f32) + 'static) -> Report<E> {
+    let searched = Cell::new(None::<(u64, Instant)>);
+    Box::new(move |board, engine, result| {
+        let t0 = match searched.get() {
+            Some((bits, t0)) if bits == board.board => t0,
+            _ => {
+                println!("{header}");
+                let t0 = Instant::now() - Duration::from_secs_f32(result.elapsed);
+                searched.set(Some((board.board, t0)));
+                t0
+            }
+        };
+        print(engine, result, board.get_player(), t0.elapsed().as_secs_f32());
+    })
+}
+
+fn print_variation(board: &Board, variation: &[TMove]) {
+    let mut board = *board;
+    for tmove in variation {
+        println!("{}", tmove);
+        board = board.make_move(tmove);
+        board.print_board();
     }
-    return last_result;
 }
 
+fn solve_mcts(mcts: &mut Mcts, board: &Board, limits: &SearchLimits, show_pv: bool) {
+    board.print_board();
+    match search_mcts(mcts, board, limits) {
+        Some(result) if show_pv => { print_variation(board, &result.pv); }
+        _ => {}
+    }
+}
 
-fn search(board: &Board, start_depth: i32, depth: i32, step: usize, pvs: bool) -> Option<TMove> {
-    let mut negamax = Negamax::new();
-    return search_in_time(&mut negamax, board, start_depth, depth, step, 60.0*60.0*24.0, pvs).1;
+fn solve(negamax: &mut Negamax, board: &Board, limits: &SearchLimits, pvs: bool, show_pv: bool, verbose: bool) {
+    board.print_board();
+    match search(negamax, board, limits, pvs, verbose) {
+        Some(result) if show_pv => { print_variation(board, &result.pv); }
+        _ => {}
+    }
 }
 
-fn solve(board: &Board, depth: i32, pvs: bool) {
+fn run_perft(board: &Board, depth: i32) {
     board.print_board();
-    search(board, 1, depth, 2, pvs);
-}
-
-fn get_human_move(possible_moves: &Vec<TMove>) -> Option<TMove> {
-    let mut input = "".to_string();
-    while input.to_lowercase().as_str() != "exit" {
-        println!("Enter move: ");
-        input = read!();
-        match TMove::from_str(&input) {
-            Ok(tmove) => { 
-                if possible_moves.contains(&tmove) {
-                    return Some(tmove); 
-                }
-                println!("Not a valid move!");
-            }
-            Err(_) => { println!("Syntax Error!")}
-        } 
+    let t0 = Instant::now();
+    let divided = divide(board, depth as u32);
+    for (tmove, count) in &divided {
+        println!("{tmove}\t{count}");
     }
-    return None;
+    let total = divided.iter().map(|(_, count)| count).sum::<u64>();
+    let elapsed = t0.elapsed().as_secs_f32();
+    println!("Moves: {}\tNodes: {total}\tTime: {elapsed:.2}s\tkN/s: {:.0}", divided.len(), total as f32 / elapsed / 1000.0);
 }
 
-fn is_next_player_human() -> bool {
-    let mut input = "".to_string();
-    while input.to_lowercase().as_str() != "exit" {
-        println!("Enter next player h/c: ");
-        input = read!();
-        match input.as_str() {
-            "c" => { return false; }
-            "h" => { return true; }
-            _ => {}
-        } 
+fn list_unmoves(board: &Board) {
+    board.print_board();
+    let letters = ["a", "b", "c", "d", "e"];
+    let unmoves = unmoves(board);
+    for unmove in &unmoves {
+        let captured = (0..25u8).filter(|square| unmove.captured & (1 << (24 - square)) != 0)
+            .map(|square| format!("x{}{}", letters[(square % 5) as usize], 5 - square / 5))
+            .collect::<Vec<String>>().join(" ");
+        println!("{}\t{captured}\t{:#b}", unmove.tmove, unmove.previous.board);
+    }
+    println!("Unmoves: {}", unmoves.len());
+}
+
+fn describe_value(value: Option<Value>, player: u8) -> String {
+    let (mover, opponent) = match player {
+        0 => ("defenders", "attackers"),
+        _ => ("attackers", "defenders")
+    };
+    let distance = |plies: Option<u32>| match plies {
+        Some(plies) => format!(" in {} ({plies} plies)", plies.div_ceil(2)),
+        None => "".to_string()
+    };
+    match value {
+        Some(Value::Win(plies)) => format!("{mover} win{}", distance(plies)),
+        Some(Value::Loss(plies)) => format!("{opponent} win{}", distance(plies)),
+        Some(Value::Draw) => "draw".to_string(),
+        None => "unknown".to_string()
+    }
+}
+
+fn prove(board: &Board, max_plies: i32, node_limit: Option<u64>) {
+    board.print_board();
+    let t0 = Instant::now();
+    let mut solver = Solver::new(max_plies as u32, node_limit);
+    let solution = solver.solve(board);
+    let elapsed = t0.elapsed().as_secs_f32();
+
+    match solution.value {
+        Some(value) => println!("Value: {}", describe_value(Some(value), board.get_player())),
+        None => println!("Value: unknown, node budget exhausted")
+    }
+    match solution.tmove {
+        Some(tmove) => println!("Move: {tmove}"),
+        None => println!("Move: -")
+    }
+    println!("Nodes: {}\tStored: {}\tTime: {elapsed:.2}s", solver.nodes, solver.len());
+}
+
+fn prove_win(board: &Board, memory_mb: usize, tablebase: Option<Arc<Tablebase>>, proof_file: &str) {
+    board.print_board();
+    let t0 = Instant::now();
+    let mut search = ProofSearch::with_memory(memory_mb << 20);
+    search.tablebase = tablebase;
+    let outcome = search.search(board);
+    let elapsed = t0.elapsed().as_secs_f32();
+
+    let mover = match board.get_player() {
+        0 => "defenders",
+        _ => "attackers"
+    };
+    match outcome {
+        Outcome::Proven => println!("Proven: {mover} win"),
+        Outcome::Disproven => println!("Disproven: {mover} do not win"),
+        Outcome::Unknown => println!("Unknown: memory limit of {memory_mb} MB reached")
+    }
+    match (outcome, search.best_move()) {
+        (Outcome::Proven, Some(tmove)) => println!("Move: {tmove}"),
+        (Outcome::Unknown, Some(tmove)) => println!("Most proving move: {tmove}"),
+        _ => println!("Move: -")
+    }
+    let (proof, disproof) = search.root_numbers().unwrap_or((0, 0));
+    let number = |n: u32| match n {
+        INFINITE => "inf".to_string(),
+        n => n.to_string()
+    };
+    println!("Nodes: {}\tProof: {}\tDisproof: {}\tTime: {elapsed:.2}s", search.len(), number(proof), number(disproof));
+
+    if proof_file.is_empty() { return; }
+    let written = match search.proof_tree() {
+        Some(proof) => File::create(proof_file).and_then(|file| proof.write(BufWriter::new(file))).map(|_| proof.len()),
+        None => { println!("No proof to write"); return; }
+    };
+    match written {
+        Ok(positions) => println!("Wrote proof with {positions} positions to {proof_file}"),
+        Err(error) => println!("Could not write proof: {error}")
     }
-    return true;
 }
 
-fn sandbox(start_board: &Board, pvs: bool) {
-    let mut negamax = Negamax::new();
-    let mut board = *start_board;
+fn check_proof(proof_file: &str) {
+    let t0 = Instant::now();
+    let proof = match File::open(proof_file).and_then(|file| ProofTree::read(BufReader::new(file))) {
+        Ok(proof) => proof,
+        Err(error) => { println!("Could not read proof: {error}"); return; }
+    };
+    proof.root.print_board();
+    let prover = match proof.prover {
+        0 => "defenders",
+        _ => "attackers"
+    };
+    match proof.check() {
+        Ok(checked) => println!("Valid: {prover} win, {checked} positions checked in {:.2}s", t0.elapsed().as_secs_f32()),
+        Err(reason) => println!("Invalid: {reason}")
+    }
+}
+
+fn generate_tablebase(dir: &Path, max_attackers: u8, max_defenders: u8) {
+    let t0 = Instant::now();
+    let mut tablebase = Tablebase::load(dir).unwrap_or_default();
+    println!("Table\tPositions\tWins\tLosses\tDraws\tLongest\tTime");
+    tablebase.generate(max_attackers, max_defenders, |signature, stats| {
+        println!("{}a{}d\t{}\t{}\t{}\t{}\t{}\t{:.2}s", signature.attackers, signature.defenders, stats.positions,
+            stats.wins, stats.losses, stats.draws, stats.longest, t0.elapsed().as_secs_f32());
+    });
+    match tablebase.save(dir) {
+        Ok(()) => println!("Saved {} tables to {}", tablebase.signatures().len(), dir.display()),
+        Err(error) => println!("Could not save tablebase: {error}")
+    }
+}
+
+fn probe_tablebase(board: &Board, dir: &Path) {
+    board.print_board();
+    let tablebase = match Tablebase::load(dir) {
+        Ok(tablebase) => tablebase,
+        Err(error) => { println!("Could not load tablebase: {error}"); return; }
+    };
+    let player = board.get_player();
+    match Signature::of(board) {
+        Some(signature) => println!("Material: {} attackers, {} defenders and the king", signature.attackers, signature.defenders),
+        None => println!("Material: no king")
+    }
+    let value = tablebase.probe(board);
+    match value {
+        Some(value) => println!("Value: {}", describe_value(Some(value), player)),
+        None => { println!("Value: not in the tablebase"); return; }
+    }
+    if board.get_winner().is_some() { return; }
+
+    // values after each move, from the point of view of the side to move here
+    let mut moves = board.moves().iter().map(|tmove| {
+        let value = match tablebase.probe(&board.make_move(tmove)) {
+            Some(Value::Win(plies)) => Value::Loss(plies.map(|plies| plies + 1)),
+            Some(Value::Loss(plies)) => Value::Win(plies.map(|plies| plies + 1)),
+            _ => Value::Draw
+        };
+        (*tmove, value)
+    }).collect::<Vec<(TMove, Value)>>();
+    moves.sort_by_key(|(_, value)| match value {
+        Value::Win(plies) => (0, plies.unwrap_or(0) as i64),
+        Value::Draw => (1, 0),
+        Value::Loss(plies) => (2, -(plies.unwrap_or(0) as i64))
+    });
+    for (tmove, value) in moves {
+        println!("{tmove}\t{}", describe_value(Some(value), player));
+    }
+}
+
+enum Command {
+    Human,
+    Computer,
+    Undo,
+    Redo,
+    Exit,
+}
+
+fn next_command() -> Command {
     loop {
-        board.print_board();
-        match board.get_winner() {
-            Some(0) => { 
-                println!("White Won!"); 
-                return; 
-            }
-            Some(1) => { 
-                println!("Black Won!");
-                return; 
-            }
+        println!("Enter next player h/c, u to undo or r to redo: ");
+        let input: String = read!();
+        match input.to_lowercase().as_str() {
+            "c" => { return Command::Computer; }
+            "h" => { return Command::Human; }
+            "u" => { return Command::Undo; }
+            "r" => { return Command::Redo; }
+            "exit" => { return Command::Exit; }
             _ => {}
         }
+    }
+}
 
-        let possible_moves = board.get_possible_moves();
-        if possible_moves.is_empty() {
-            match board.get_player() {
-                0 => println!("Black Won!"),
-                _ => println!("White Won!")
-            }
+fn describe_result(result: &GameResult) -> &'static str {
+    match result.winner() {
+        Some(0) => "White Won!",
+        Some(_) => "Black Won!",
+        None => "Draw!"
+    }
+}
+
+fn sandbox(computer: &mut dyn Player, start_board: &Board, limits: &SearchLimits, clock: Option<Clock>) {
+    let mut game = match clock {
+        Some(clock) => Game::with_clock(*start_board, clock),
+        None => Game::new(*start_board)
+    };
+    let mut human = HumanPlayer;
+    loop {
+        let board = game.board();
+        board.print_board();
+        if let Some(result) = game.result() {
+            println!("{}", describe_result(&result));
             return;
         }
-        
-        let tmove_option = match is_next_player_human() {
-            true => { get_human_move(&possible_moves) }
-            false => { search_in_time(&mut negamax, &board, 4, 20, 2, 10.0, pvs).1 }
+
+        let command = next_command();
+        // the time at the prompt is not the mover's
+        let t0 = Instant::now();
+        let choice = match command {
+            Command::Human => { human.choose(&game, limits) }
+            Command::Computer => {
+                let limits = SearchLimits { time: game.budget().or(limits.time), root_moves: None, ..limits.clone() };
+                computer.choose(&game, &limits)
+            }
+            Command::Undo => {
+                match game.undo() {
+                    Some(tmove) => println!("Took back move {}", tmove),
+                    None => println!("No move to take back!")
+                }
+                continue;
+            }
+            Command::Redo => {
+                match game.redo() {
+                    Some(tmove) => println!("Played move {} again", tmove),
+                    None => println!("No move to play again!")
+                }
+                continue;
+            }
+            Command::Exit => { return; }
         };
         
-        match tmove_option {
-            Some(tmove) => { 
-                println!("Executing move {}", tmove.to_string());
-                board = board.make_move(&tmove); 
+        match choice {
+            Some(choice) => { 
+                println!("Executing move {}", choice.tmove);
+                game.play_timed(&choice.tmove, Some(t0.elapsed())).expect("players only choose possible moves");
+                if let Some(clocks) = game.clocks() {
+                    println!("Clocks: white {:.1}s, black {:.1}s", clocks[0].remaining.as_secs_f32(), clocks[1].remaining.as_secs_f32());
+                }
             },
             None => { return; }
         }
     }
 }
 
+fn run_match(first: &mut dyn Player, second: &mut dyn Player, board: &Board, limits: &SearchLimits, games: u32) {
+    board.print_board();
+    let t0 = Instant::now();
+    let (first_name, second_name) = (first.name(), second.name());
+    // people playing along have to see the moves
+    let show_moves = first_name == "human" || second_name == "human";
+    println!("Game\tDefenders\tAttackers\tResult\tPlies\tTime");
+    let on_move = |game: &Game, _: &Option<DepthResult>| {
+        if let (true, Some(ply)) = (show_moves, game.plies().last()) {
+            println!("Executing move {}", ply.tmove);
+            ply.board.print_board();
+        }
+    };
+    let score = play_match(first, second, board, limits, games, on_move, |index, defending, game| {
+        let (defenders, attackers) = match defending {
+            0 => (&first_name, &second_name),
+            _ => (&second_name, &first_name)
+        };
+        let result = match game.result().and_then(|result| result.winner()) {
+            Some(0) => "defenders win",
+            Some(_) => "attackers win",
+            None => "draw"
+        };
+        println!("{}\t{defenders}\t{attackers}\t{result}\t{}\t{:.2}s", index + 1, game.len(), t0.elapsed().as_secs_f32());
+        if show_moves && index + 1 < games {
+            board.print_board();
+        }
+    });
+    println!("Score: {first_name} {} - {} {second_name} in {} games", score.first, score.second, score.games);
+}
+
+/// Board bits given as a number, binary with 0b, hex with 0x or decimal, underscores allowed.
+fn parse_board(position: &str) -> Option<u64> {
+    let digits = position.replace('_', "");
+    match digits.get(..2) {
+        Some("0b") => u64::from_str_radix(&digits[2..], 2).ok(),
+        Some("0x") => u64::from_str_radix(&digits[2..], 16).ok(),
+        _ => digits.parse().ok()
+    }
+}
 
 fn main() {
     let mut mode = "solve".to_string();
     let mut base_board = "start".to_string();
-    let mut depth = 12;
+    let mut depth = 0;
     let mut pvs = false;
+    let mut null_move = false;
+    let mut lmr = false;
+    let mut no_ordering = false;
+    let mut show_pv = false;
+    let mut verbose = false;
+    let mut multipv = 1;
+    let mut threads = 1;
+    let mut clock_time = 0.0f32;
+    let mut increment = 0.0f32;
+    let mut movetime = 0.0f32;
+    let mut nodes = 0u64;
+    let mut win_in = 0;
+    let mut root_moves = "".to_string();
+    let mut tb_dir = "".to_string();
+    let mut tb_attackers = 3u8;
+    let mut tb_defenders = 1u8;
+    let mut memory = 512usize;
+    let mut proof_file = "".to_string();
+    let mut engine = "negamax".to_string();
+    let mut exploration = std::f64::consts::SQRT_2;
+    let mut playout = "random".to_string();
+    let mut opponent = "negamax".to_string();
+    let mut games = 2u32;
 
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("What do you want to do?");
-        ap.refer(&mut mode).add_option(&["-a", "--action"], Store, "Action: solve, sanbox");
-        ap.refer(&mut base_board).add_option(&["-b", "--board"], Store, "Staring postion. One of: start, benchmark, 18move");
-        ap.refer(&mut depth).add_option(&["-d", "--depth"], Store, "Search depth for computer generation.");
+        ap.refer(&mut mode).add_option(&["-a", "--action"], Store, "Action: solve, prove, sandbox, match, pns, check-proof, perft, unmoves, tb-gen, tb-probe");
+        ap.refer(&mut base_board).add_option(&["-b", "--board"], Store, "Staring postion. One of: start, benchmark, 18move, or the board bits as a binary (0b...), hex (0x...) or decimal number");
+        ap.refer(&mut depth).add_option(&["-d", "--depth"], Store, "Search depth for computer generation, defaults to 12 for solve, 20 for sandbox and match and 4 for perft. For prove the plies searched for wins before draws are proven, defaults to 10.");
+        ap.refer(&mut movetime).add_option(&["--movetime"], Store, "Time per search in seconds, defaults to unlimited for solve, 10 for sandbox and 1 for match.");
+        ap.refer(&mut nodes).add_option(&["--nodes"], Store, "Approximate node budget per search, 0 for unlimited.");
+        ap.refer(&mut win_in).add_option(&["--win-in"], Store, "Stop once a win in at most this many moves is found.");
+        ap.refer(&mut root_moves).add_option(&["--moves"], Store, "Comma separated root moves to consider, e.g. b3-b5,c4-a4.");
+        ap.refer(&mut engine).add_option(&["-e", "--engine"], Store, "Engine for solve, sandbox and match: negamax or mcts.");
+        ap.refer(&mut opponent).add_option(&["--opponent"], Store, "Player the engine meets in a match: negamax, pvs, mcts or human.");
+        ap.refer(&mut games).add_option(&["--games"], Store, "Games of a match, the engine takes the defenders in every other game. Defaults to 2.");
+        ap.refer(&mut exploration).add_option(&["--exploration"], Store, "Exploration weight of the MCTS engine, defaults to the square root of 2.");
+        ap.refer(&mut playout).add_option(&["--playout"], Store, "Playouts of the MCTS engine: random or eval.");
         ap.refer(&mut pvs).add_option(&["-p", "--pvs"], StoreTrue, "Search depth for computer generation.");
+        ap.refer(&mut null_move).add_option(&["-n", "--null-move"], StoreTrue, "Enable null-move pruning.");
+        ap.refer(&mut lmr).add_option(&["-l", "--lmr"], StoreTrue, "Enable late-move reductions.");
+        ap.refer(&mut no_ordering).add_option(&["--no-ordering"], StoreTrue, "Disable killer moves and the history heuristic, to compare node counts.");
+        ap.refer(&mut multipv).add_option(&["--multipv"], Store, "Number of best root moves to report when solving.");
+        ap.refer(&mut threads).add_option(&["-t", "--threads"], Store, "Number of search threads, 1 searches deterministically.");
+        ap.refer(&mut clock_time).add_option(&["--clock"], Store, "Game clock of both players in seconds for sandbox, 0 for 10s per move of the computer.");
+        ap.refer(&mut increment).add_option(&["--increment"], Store, "Increment per move in seconds for the sandbox clock.");
+        ap.refer(&mut show_pv).add_option(&["--show-pv"], StoreTrue, "Print the boards of the principal variation after solving.");
+        ap.refer(&mut memory).add_option(&["--memory"], Store, "Memory limit of the proof-number search tree in MB, defaults to 512.");
+        ap.refer(&mut proof_file).add_option(&["--proof-file"], Store, "File pns writes a proven win to and check-proof reads it from.");
+        ap.refer(&mut tb_dir).add_option(&["--tb-dir"], Store, "Tablebase directory, probed during search if given. Defaults to tablebases for tb-gen and tb-probe.");
+        ap.refer(&mut tb_attackers).add_option(&["--tb-attackers"], Store, "Most attackers of the tables tb-gen generates, defaults to 3.");
+        ap.refer(&mut tb_defenders).add_option(&["--tb-defenders"], Store, "Most defenders besides the king of the tables tb-gen generates, defaults to 1.");
+        ap.refer(&mut verbose).add_option(&["-v", "--stats"], StoreTrue, "Print all search counters after every iteration.");
         ap.parse_args_or_exit();
     }
 
-    println!("Mode\tBoard    \tDepth\tPVS");
-    println!("{mode}\t{base_board}\t{depth}\t{pvs}");
+    let sandbox_mode = mode == "sandbox";
+    let game_mode = sandbox_mode || mode == "match";
+    if depth == 0 {
+        depth = match mode.as_str() {
+            "sandbox" | "match" => 20,
+            "perft" => 4,
+            "prove" => 10,
+            _ => 12
+        };
+    }
+    if movetime == 0.0 && sandbox_mode {
+        movetime = 10.0;
+    }
+    if movetime == 0.0 && mode == "match" {
+        movetime = 1.0;
+    }
+
+    println!("Mode\tBoard    \tDepth\tPVS\tNull\tLMR");
+    println!("{mode}\t{base_board}\t{depth}\t{pvs}\t{null_move}\t{lmr}");
 
     let board = Board { board: 
         match base_board.as_str() {
             "benchmark" => 0b0_0000010000_0100000001_0110101001_0111000010_0001000001,
             "start" => 0b0_0100010001_0000100000_0110111001_0000100000_0100010001,
             "18move" => 0b0_0100010000_0000000100_0110101001_0111000010_0001000001,
-            _ => { println!("Defaulting to start position"); 0b0_0100010001_0000100000_0110111001_0000100000_0100010001 }
+            position => match parse_board(position) {
+                Some(board) => board,
+                None => { println!("Defaulting to start position"); 0b0_0100010001_0000100000_0110111001_0000100000_0100010001 }
+            }
         }
     };
 
+    let root_moves = match root_moves.is_empty() {
+        true => None,
+        false => match root_moves.split(',').map(|tmove| TMove::from_str(tmove.trim())).collect::<Result<Vec<TMove>, _>>() {
+            Ok(root_moves) => Some(root_moves),
+            Err(_) => { println!("Could not parse moves {root_moves}"); return; }
+        }
+    };
+    let possible_moves = board.moves();
+    if let Some(impossible) = root_moves.iter().flatten().find(|tmove| !possible_moves.contains(tmove)) {
+        println!("Move {impossible} is not possible in this position");
+        return;
+    }
+    let limits = SearchLimits {
+        start_depth: if game_mode { 4 } else { 1 },
+        max_depth: depth,
+        step: 2,
+        time: match movetime > 0.0 {
+            true => Some(TimeBudget::fixed(Duration::from_secs_f32(movetime))),
+            false => None
+        },
+        nodes: match nodes { 0 => None, nodes => Some(nodes) },
+        win_in: match win_in { 0 => None, win_in => Some(win_in) },
+        root_moves,
+        multipv: multipv.max(1)
+    };
+
+    let tablebase = match tb_dir.is_empty() || !matches!(mode.as_str(), "solve" | "sandbox" | "match" | "pns") {
+        true => None,
+        false => match Tablebase::load(Path::new(&tb_dir)) {
+            Ok(tablebase) => Some(Arc::new(tablebase)),
+            Err(error) => { println!("Could not load tablebase: {error}"); return; }
+        }
+    };
+    let tb_dir = match tb_dir.is_empty() {
+        true => "tablebases".to_string(),
+        false => tb_dir
+    };
+    let new_negamax = || {
+        let mut negamax = Negamax::new();
+        negamax.null_move = null_move;
+        negamax.lmr = lmr;
+        negamax.move_ordering = !no_ordering;
+        negamax.threads = threads.max(1);
+        negamax.tablebase = tablebase.clone();
+        negamax
+    };
+    let new_mcts = || {
+        let mut mcts = Mcts::new();
+        mcts.exploration = exploration;
+        mcts.playout = match playout.as_str() {
+            "eval" => Playout::Eval { epsilon: 0.2, plies: 16 },
+            _ => Playout::Random
+        };
+        mcts
+    };
+    // players print their iterations only where a single game is followed move by move
+    let new_player = |name: &str, report: bool| -> Option<Box<dyn Player>> {
+        match name {
+            "human" => Some(Box::new(HumanPlayer)),
+            "negamax" | "pvs" => {
+                let mut player = NegamaxPlayer::new(new_negamax(), name == "pvs");
+                if report {
+                    player.report = Some(report_iterations(HEADER, move |negamax, result, player, elapsed| {
+                        print_depth_result(negamax, result, player, elapsed, verbose);
+                    }));
+                }
+                Some(Box::new(player))
+            }
+            "mcts" => {
+                let mut player = MctsPlayer::new(new_mcts());
+                if report {
+                    player.report = Some(report_iterations(MCTS_HEADER, print_mcts_result));
+                }
+                Some(Box::new(player))
+            }
+            _ => None
+        }
+    };
+    let engine = match (engine.as_str(), pvs) {
+        ("mcts", _) => "mcts",
+        (_, true) => "pvs",
+        _ => "negamax"
+    };
+
     match mode.as_str() {
-        "solve" => { solve(&board, depth, pvs); },
-        "sandbox" => { sandbox(&board, pvs); },
-        _ => { println!("Defaulting to solve!"); solve(&board, depth, pvs)}
+        "solve" => match engine {
+            "mcts" => { solve_mcts(&mut new_mcts(), &board, &limits, show_pv); }
+            _ => { solve(&mut new_negamax(), &board, &limits, pvs, show_pv, verbose); }
+        },
+        "perft" => { run_perft(&board, depth); },
+        "prove" => { prove(&board, depth, limits.nodes); },
+        "pns" => { prove_win(&board, memory, tablebase, &proof_file); },
+        "check-proof" => { check_proof(&proof_file); },
+        "unmoves" => { list_unmoves(&board); },
+        "tb-gen" => { generate_tablebase(Path::new(&tb_dir), tb_attackers, tb_defenders); },
+        "tb-probe" => { probe_tablebase(&board, Path::new(&tb_dir)); },
+        "sandbox" => {
+            let clock = match clock_time > 0.0 {
+                true => Some(Clock { remaining: Duration::from_secs_f32(clock_time), increment: Duration::from_secs_f32(increment), moves_to_go: None }),
+                false => None
+            };
+            sandbox(new_player(engine, true).unwrap().as_mut(), &board, &limits, clock);
+        },
+        "match" => match new_player(&opponent, false) {
+            Some(mut second) => { run_match(new_player(engine, false).unwrap().as_mut(), second.as_mut(), &board, &limits, games); }
+            None => { println!("Unknown player {opponent}"); }
+        },
+        _ => { println!("Defaulting to solve!"); solve(&mut new_negamax(), &board, &limits, pvs, show_pv, verbose)}
     }
-}   
+}
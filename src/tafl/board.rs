@@ -1,30 +1,123 @@
 
-use super::tmove::TMove;
+use super::score::MAX_EVAL;
+use super::tmove::{MoveList, TMove};
 use colored::Colorize;
 use unroll::unroll_for_loops;
 
+// lower bit of every square of the board encoding
+const LOW_BITS: u64 = 0b0101010101_0101010101_0101010101_0101010101_0101010101;
+// one bit per row in a bitboard column, shifted to the column of interest
+const COLUMN_BITS: u32 = 0b00001_00001_00001_00001_00001;
+// gathers the bits of a column into five consecutive bits, no two partial products overlap
+const COLUMN_MAGIC: u64 = (1 << 20) | (1 << 16) | (1 << 12) | (1 << 8) | (1 << 4);
+
+/// Squares reachable along a line of five squares from position `p`, for every occupancy of
+/// that line. Position `q` corresponds to bit `4 - q` in both the occupancy and the result.
+static LINE_SLIDES: [[u8; 32]; 5] = line_slides();
+
+const fn line_slides() -> [[u8; 32]; 5] {
+    let mut table = [[0u8; 32]; 5];
+    let mut p = 0;
+    while p < 5 {
+        let mut occupancy = 0;
+        while occupancy < 32 {
+            let mut reachable = 0u8;
+            let mut q = p as i32 - 1;
+            while q >= 0 && occupancy & (1 << (4 - q)) == 0 {
+                reachable |= 1 << (4 - q);
+                q -= 1;
+            }
+            q = p as i32 + 1;
+            while q < 5 && occupancy & (1 << (4 - q)) == 0 {
+                reachable |= 1 << (4 - q);
+                q += 1;
+            }
+            table[p][occupancy] = reachable;
+            occupancy += 1;
+        }
+        p += 1;
+    }
+    table
+}
+
+// squares on the outer ring and on the ring around the centre, in bitboard layout
+const EDGE: u32 = 0b11111_10001_10001_10001_11111;
+const INNER_RING: u32 = 0b00000_01110_01010_01110_00000;
+
+/// Orthogonal neighbours of every square, in bitboard layout.
+static NEIGHBOURS: [u32; 25] = neighbours();
+
+const fn neighbours() -> [u32; 25] {
+    let mut table = [0u32; 25];
+    let mut i = 0;
+    while i < 25 {
+        let (x, y) = (i % 5, i / 5);
+        if x > 0 { table[i] |= 1 << (24 - (i - 1)); }
+        if x < 4 { table[i] |= 1 << (24 - (i + 1)); }
+        if y > 0 { table[i] |= 1 << (24 - (i - 5)); }
+        if y < 4 { table[i] |= 1 << (24 - (i + 5)); }
+        i += 1;
+    }
+    table
+}
+
+/// Packs the low bits of the 25 two bit squares into a 25 bit bitboard.
+#[inline]
+fn compress_squares(x: u64) -> u32 {
+    let x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    let x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    let x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    let x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    let x = (x | (x >> 16)) & 0x0000_0000_FFFF_FFFF;
+    x as u32
+}
+
+/// Inverse of `compress_squares`, spreads bit `k` to bit `2k`.
+#[inline]
+fn expand_squares(x: u32) -> u64 {
+    let x = x as u64;
+    let x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    let x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    let x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    let x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Occupancy of row `y` with column `x` in bit `4 - x`.
+#[inline]
+fn row_occupancy(occupied: u32, y: u8) -> usize {
+    ((occupied >> (20 - 5 * y)) & 0b11111) as usize
+}
+
+/// Occupancy of column `x` with row `y` in bit `4 - y`, like the rows of a bitboard.
+#[inline]
+fn column_occupancy(occupied: u32, x: u8) -> usize {
+    (((((occupied >> (4 - x)) & COLUMN_BITS) as u64 * COLUMN_MAGIC) >> 20) & 0b11111) as usize
+}
+
 #[derive(Clone, Copy)]
 pub struct Board {pub board: u64}
 
 impl Board {
-    pub fn get_winner(&self) -> Option<u8> {
-        let mut king_pos: Option<(u8, u8)> = None;
-        for i in 0..5 {
-            for j in 0..5 {
-                if self.get(i, j) == 3 {
-                    king_pos = Some((i, j));
-                }
+    pub fn get_king_pos(&self) -> Option<(u8, u8)> {
+        match self.king() {
+            0 => None,
+            king => {
+                let i = 24 - king.trailing_zeros() as u8;
+                Some((i % 5, i / 5))
             }
         }
-        match king_pos {
-            None => { return Some(1); }
-            Some((x, y)) => {
-                if x == 0 || x == 4 || y == 0 || y == 4 {
-                    return Some(0);
-                }
-                return None;
-            }
+    }
+
+    pub fn get_winner(&self) -> Option<u8> {
+        let king = self.king();
+        if king == 0 {
+            return Some(1);
+        }
+        if king & EDGE != 0 {
+            return Some(0);
         }
+        None
     }
 
     pub fn get(&self, x: u8, y: u8) -> u8 {
@@ -45,14 +138,14 @@ impl Board {
         ((self.board >> 50) & 1) as u8
     }
 
-    fn next_player(&self) -> Self {
+    pub fn next_player(&self) -> Self {
         Board { board: self.board ^ (1u64 << 50) }
     }
 
     fn check_beaten(&self, player: u8, middle_piece: u8, outer_piece: u8) -> bool {
         let p1_cond = (player == 0) && (outer_piece > 1) && (middle_piece == 1);
         let p2_cond = (player == 1) && (outer_piece == 1) && (middle_piece > 1);
-        return p1_cond | p2_cond;
+        p1_cond | p2_cond
     }
 
     pub fn make_move(&self, tmove: &TMove) -> Board {
@@ -98,6 +191,33 @@ impl Board {
         board.next_player()
     }
 
+    pub fn is_capture(&self, tmove: &TMove) -> bool {
+        let (sx, sy) = tmove.start;
+        let (ex, ey) = tmove.end;
+        let player = self.get_player();
+        let board = self.set(sx, sy, 0);
+
+        // same neighbourhood checks as make_move, without building the child board
+        (ex > 1 && board.check_beaten(player, board.get(ex - 1, ey), board.get(ex - 2, ey))) ||
+        (ex < 3 && board.check_beaten(player, board.get(ex + 1, ey), board.get(ex + 2, ey))) ||
+        (ey > 1 && board.check_beaten(player, board.get(ex, ey - 1), board.get(ex, ey - 2))) ||
+        (ey < 3 && board.check_beaten(player, board.get(ex, ey + 1), board.get(ex, ey + 2)))
+    }
+
+    /// Number of free lines from the king to the edge, i.e. ways the defenders could win with their next move.
+    pub fn count_king_escapes(&self) -> u8 {
+        match self.get_king_pos() {
+            Some((x, y)) => {
+                let occupied = self.occupied();
+                // the king's own square counts as reached, so a king on the edge escapes towards it
+                let row = LINE_SLIDES[x as usize][row_occupancy(occupied, y)] | (1 << (4 - x));
+                let column = LINE_SLIDES[y as usize][column_occupancy(occupied, x)] | (1 << (4 - y));
+                ((row & 0b10001).count_ones() + (column & 0b10001).count_ones()) as u8
+            }
+            None => 0
+        }
+    }
+
     #[unroll_for_loops]
     pub fn get_max_moves_piece(&self, x: u8, y: u8) -> (u8, u8, u8, u8){
         // check free moves in top direction, from y to y=0 y excluded
@@ -135,45 +255,87 @@ impl Board {
             }
         }
 
-        return (min_x_move, max_x_move, min_y_move, max_y_move);
+        (min_x_move, max_x_move, min_y_move, max_y_move)
     }
 
-    #[unroll_for_loops]
-    fn get_possible_moves_player<const PLAYER: u8>(&self) -> Vec<TMove> {
-        let mut moves = Vec::new();
-        for i_ in 0..5 {
-            for j_ in 0..5 {
-                let i = i_ as u8;
-                let j = j_ as u8;
-                if ((PLAYER == 0) & (self.get(i, j) > 1)) | ((PLAYER == 1) & (self.get(i, j) == 1)){
-                    let (min_x_move, max_x_move, min_y_move, max_y_move) = self.get_max_moves_piece(i, j);
-
-                    // add moves in x direction to possible moves
-                    for m_i in min_x_move..i {moves.push(TMove{start: (i, j), end: (m_i, j)})}
-                    for m_i in i+1..=max_x_move {moves.push(TMove{start: (i, j), end: (m_i, j)})}
-                    
-                    // add moves in y direction to possible moves
-                    for m_j in min_y_move..j {moves.push(TMove{start: (i, j), end: (i, m_j)})}
-                    for m_j in j+1..=max_y_move {moves.push(TMove{start: (i, j), end: (i, m_j)})}
-                }
+    /// Board of `player` to move from bitboards laid out like `occupied`, `defenders` including the king.
+    pub fn from_bitboards(player: u8, attackers: u32, defenders: u32, king: u32) -> Board {
+        Board { board: ((player as u64) << 50) | expand_squares(attackers | king) | (expand_squares(defenders | king) << 1) }
+    }
+
+    /// Bitboard with bit `24 - (5 * y + x)` set for every occupied square (x, y).
+    #[inline]
+    pub fn occupied(&self) -> u32 {
+        compress_squares((self.board | (self.board >> 1)) & LOW_BITS)
+    }
+
+    /// Bitboard of the attackers, same layout as `occupied`.
+    #[inline]
+    pub fn attackers(&self) -> u32 {
+        compress_squares(self.board & LOW_BITS) & !compress_squares((self.board >> 1) & LOW_BITS)
+    }
+
+    /// Bitboard of the king, same layout as `occupied`.
+    #[inline]
+    pub fn king(&self) -> u32 {
+        compress_squares(self.board & (self.board >> 1) & LOW_BITS)
+    }
+
+    /// Bitboard of the defenders including the king, same layout as `occupied`.
+    #[inline]
+    pub fn defenders(&self) -> u32 {
+        compress_squares((self.board >> 1) & LOW_BITS)
+    }
+
+    /// Generates the moves of the side to move without allocating. Every piece slides along its row
+    /// and column until the next occupied square, looked up from the occupancy of that line.
+    pub fn moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        let occupied = self.occupied();
+        let mut pieces = match self.get_player() {
+            0 => self.defenders(),
+            _ => self.attackers()
+        };
+        while pieces != 0 {
+            let i = 24 - pieces.trailing_zeros() as u8;
+            pieces &= pieces - 1;
+            let (x, y) = (i % 5, i / 5);
+
+            let mut row = LINE_SLIDES[x as usize][row_occupancy(occupied, y)];
+            while row != 0 {
+                let m_i = 4 - row.trailing_zeros() as u8;
+                row &= row - 1;
+                moves.push(TMove { start: (x, y), end: (m_i, y) });
+            }
+
+            let mut column = LINE_SLIDES[y as usize][column_occupancy(occupied, x)];
+            while column != 0 {
+                let m_j = 4 - column.trailing_zeros() as u8;
+                column &= column - 1;
+                moves.push(TMove { start: (x, y), end: (x, m_j) });
             }
         }
         moves
     }
 
     pub fn get_possible_moves(&self) -> Vec<TMove>{
-        if self.get_player() == 0 {
-            return self.get_possible_moves_player::<0>();
-        } else {
-            return self.get_possible_moves_player::<1>();
-        }
+        self.moves().to_vec()
     }
 
     #[inline]
     fn get_only_black_board(&self) -> u64 {
         let bm1 = 0b1010101010_1010101010_1010101010_1010101010_1010101010u64;
         let bm2 = bm1 >> 1;
-        ((self.board & bm2)) & (!(self.board & bm1) >> 1)
+        (self.board & bm2) & (!(self.board & bm1) >> 1)
+    }
+
+    pub fn count_pieces(&self, player: u8) -> u32 {
+        let bm2 = 0b0101010101_0101010101_0101010101_0101010101_0101010101u64;
+        let black = self.get_only_black_board().count_ones();
+        match player {
+            0 => ((self.board | (self.board >> 1)) & bm2).count_ones() - black,
+            _ => black
+        }
     }
 
     #[inline]
@@ -181,58 +343,47 @@ impl Board {
         // row mask shifted to correct row
         let row_mask = 0b1111111111u64;
         // we only want to consider everything to the left or right of i
-        let row_mask_left = row_mask & (row_mask << (5 - i) * 2);
-        let row_mask_right = row_mask >> (i + 1) * 2;
+        let row_mask_left = row_mask & (row_mask << ((5 - i) * 2));
+        let row_mask_right = row_mask >> ((i + 1) * 2);
 
-        let blocking_left = (b_only_black & (row_mask_left << (4 - j) * 10)) == 0;
-        let blocking_right = (b_only_black & (row_mask_right << (4 - j) * 10)) == 0;
+        let blocking_left = (b_only_black & (row_mask_left << ((4 - j) * 10))) == 0;
+        let blocking_right = (b_only_black & (row_mask_right << ((4 - j) * 10))) == 0;
         let blocking_x = blocking_left as i32 + blocking_right as i32;
 
         let col_mask = 0b0000000011_0000000011_0000000011_0000000011_0000000011u64;
-        let col_mask_up = col_mask & (col_mask << (5 - j) * 10);
-        let col_mask_down = col_mask >> (j + 1) * 10;
+        let col_mask_up = col_mask & (col_mask << ((5 - j) * 10));
+        let col_mask_down = col_mask >> ((j + 1) * 10);
 
-        let blocking_top = (b_only_black & (col_mask_up << (4 - i)*2)) == 0;
-        let blocking_down = (b_only_black & (col_mask_down << (4 - i)*2)) == 0;
+        let blocking_top = (b_only_black & (col_mask_up << ((4 - i)*2))) == 0;
+        let blocking_down = (b_only_black & (col_mask_down << ((4 - i)*2))) == 0;
         let blocking_y = blocking_top as i32 + blocking_down as i32;
         
-        return blocking_x + blocking_y;
+        blocking_x + blocking_y
     }
     
-    #[unroll_for_loops]
     #[inline]
     pub fn eval(&self) -> i32 {
-        let b_board = Board { board: self.get_only_black_board() };
-        let mut score = b_board.board.count_ones() as i32 * -7;
-
-
-        for i_ in 0..5 {
-            for j_ in 0..5 {
-                let i = i_ as u8;
-                let j = j_ as u8;
-                let piece = self.get(i, j);
-                match piece {
-                    2..=3 => {
-                        if i == 0 || i == 4 || j == 0 || j == 4 { score += 12 }
-                        else if i == 1 || i == 3 || j == 1 || j == 3 { score += 6 }
-
-                        score += 2 * self.count_white_non_blocked(b_board.board, i, j);
-
-                        
-                        if piece == 3 && (
-                            (i > 0) && (b_board.get(i - 1, j) != 0) ||
-                            (i < 4) && (b_board.get(i + 1, j) != 0) ||
-                            (j > 0) && (b_board.get(i, j - 1) != 0) ||
-                            (j < 4) && (b_board.get(i, j + 1) != 0)
-                        ) { 
-                            score -= 5;
-                        }
-                    },
-                    _ => {}
-                }
-            }
+        let b_only_black = self.get_only_black_board();
+        let attackers = self.attackers();
+        let defenders = self.defenders();
+        let mut score = attackers.count_ones() as i32 * -7;
+
+        score += (defenders & EDGE).count_ones() as i32 * 12;
+        score += (defenders & INNER_RING).count_ones() as i32 * 6;
+
+        let mut pieces = defenders;
+        while pieces != 0 {
+            let i = 24 - pieces.trailing_zeros() as u8;
+            pieces &= pieces - 1;
+            score += 2 * self.count_white_non_blocked(b_only_black, i % 5, i / 5);
+        }
+
+        let king = self.king();
+        if king != 0 && NEIGHBOURS[24 - king.trailing_zeros() as usize] & attackers != 0 {
+            score -= 5;
         }
-        return score * (-2 * self.get_player() as i32 + 1);
+        // heuristic scores must never be mistaken for decided games
+        score.clamp(-MAX_EVAL, MAX_EVAL) * (-2 * self.get_player() as i32 + 1)
     }
 
     // pub fn eval_(&self) -> i32 {
@@ -266,10 +417,11 @@ impl Board {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::testing::{Lcg, START};
 
     #[test]
     fn test_game() {
-        let mut board = Board { board: 0b0_0100010001_0000100000_0110111001_0000100000_0100010001 };
+        let mut board = START;
         let moves = vec![
             TMove { start: (3, 2), end: (3, 0) }, // d
             TMove { start: (4, 2), end: (3, 2) }, // d
@@ -289,7 +441,7 @@ mod tests {
                 1 => tmove.to_string().black(),
                 _ => tmove.to_string().white()
             });
-            println!("");
+            println!();
 
             board = board.make_move(&tmove);
             board.print_board();
@@ -319,4 +471,46 @@ mod tests {
         assert_eq!(block_1, 2);
         assert_eq!(block_2, 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bitboard_eval_matches_square_loop() {
+        // eval and escape count computed square by square, as a reference for the bitboard versions
+        let reference_eval = |board: &Board| {
+            let b_only_black = board.get_only_black_board();
+            let mut score = b_only_black.count_ones() as i32 * -7;
+            for i in 0..5 {
+                for j in 0..5 {
+                    let piece = board.get(i, j);
+                    if piece < 2 { continue; }
+                    if i == 0 || i == 4 || j == 0 || j == 4 { score += 12 }
+                    else if i == 1 || i == 3 || j == 1 || j == 3 { score += 6 }
+                    score += 2 * board.count_white_non_blocked(b_only_black, i, j);
+                    let attacker_next = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dy)| {
+                        let (x, y) = (i as i32 + dx, j as i32 + dy);
+                        (0..5).contains(&x) && (0..5).contains(&y) && board.get(x as u8, y as u8) == 1
+                    });
+                    if piece == 3 && attacker_next { score -= 5; }
+                }
+            }
+            score * (-2 * board.get_player() as i32 + 1)
+        };
+        let reference_escapes = |board: &Board| {
+            let (x, y) = board.get_king_pos().unwrap();
+            let (min_x_move, max_x_move, min_y_move, max_y_move) = board.get_max_moves_piece(x, y);
+            (min_x_move == 0) as u8 + (max_x_move == 4) as u8 + (min_y_move == 0) as u8 + (max_y_move == 4) as u8
+        };
+
+        // walk through a few deterministic pseudo random games from the start position
+        let mut random = Lcg(12345);
+        for _ in 0..50 {
+            let mut board = START;
+            while board.get_winner().is_none() {
+                assert_eq!(board.eval(), reference_eval(&board));
+                assert_eq!(board.count_king_escapes(), reference_escapes(&board));
+                let moves = board.get_possible_moves();
+                if moves.is_empty() { break; }
+                board = board.make_move(&random.pick(&moves));
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+// assumed number of moves left in the game when the time control does not say
+const DEFAULT_MOVES_TO_GO: u32 = 25;
+// kept in reserve for printing, communication and thread start up
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/// Time a search may use. No new iteration is started after `soft`, and a running iteration is
+/// aborted at `hard`.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeBudget {
+    pub soft: Duration,
+    pub hard: Duration,
+}
+
+impl TimeBudget {
+    /// Budget for a fixed time per move. An iteration usually takes a few times as long as the one
+    /// before, so none is started after half the time.
+    pub fn fixed(movetime: Duration) -> TimeBudget {
+        TimeBudget { soft: movetime / 2, hard: movetime }
+    }
+}
+
+/// Game clock of one player.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub remaining: Duration,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+}
+
+impl Clock {
+    /// Spreads the remaining time over the moves still to play. The hard limit allows a search to
+    /// overrun its share when an iteration is almost done, but never risks more than a third of the
+    /// remaining time.
+    pub fn budget(&self) -> TimeBudget {
+        let available = self.remaining.saturating_sub(MOVE_OVERHEAD);
+        let moves_to_go = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let hard = (available / moves_to_go * 4 + self.increment).min(available / 3);
+        let soft = (available / moves_to_go + self.increment * 3 / 4).min(hard);
+        TimeBudget { soft, hard }
+    }
+
+    /// Takes the time used for a move off the clock and adds the increment. Returns false if the time
+    /// ran out before the move was made, the clock then stays at zero.
+    pub fn spend(&mut self, used: Duration) -> bool {
+        if used >= self.remaining {
+            self.remaining = Duration::ZERO;
+            return false;
+        }
+        self.remaining = self.remaining - used + self.increment;
+        if let Some(moves_to_go) = self.moves_to_go {
+            self.moves_to_go = Some(moves_to_go.saturating_sub(1).max(1));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(remaining: u64, increment: u64, moves_to_go: Option<u32>) -> Clock {
+        Clock { remaining: Duration::from_millis(remaining), increment: Duration::from_millis(increment), moves_to_go }
+    }
+
+    #[test]
+    fn test_budget() {
+        let fixed = TimeBudget::fixed(Duration::from_secs(1));
+        assert_eq!((fixed.soft, fixed.hard), (Duration::from_millis(500), Duration::from_secs(1)));
+
+        // 60s left after the overhead, spread over the default 25 moves
+        let budget = clock(60_050, 0, None).budget();
+        assert_eq!((budget.soft, budget.hard), (Duration::from_millis(2_400), Duration::from_millis(9_600)));
+        // the increment is added to both limits
+        let budget = clock(60_050, 1_000, None).budget();
+        assert_eq!((budget.soft, budget.hard), (Duration::from_millis(3_150), Duration::from_millis(10_600)));
+        // with few moves to go no more than a third of the time is risked
+        let budget = clock(60_050, 0, Some(2)).budget();
+        assert_eq!((budget.soft, budget.hard), (Duration::from_secs(20), Duration::from_secs(20)));
+        let budget = clock(60_050, 0, Some(0)).budget();
+        assert_eq!(budget.hard, Duration::from_secs(20));
+        // nothing is left once the overhead is taken off
+        let budget = clock(40, 1_000, None).budget();
+        assert_eq!((budget.soft, budget.hard), (Duration::ZERO, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_spend() {
+        let mut clock = clock(10_000, 1_000, Some(2));
+        assert!(clock.spend(Duration::from_secs(4)));
+        assert_eq!((clock.remaining, clock.moves_to_go), (Duration::from_secs(7), Some(1)));
+        // the control restarts with the last move, so at least one move stays to go
+        assert!(clock.spend(Duration::from_secs(1)));
+        assert_eq!((clock.remaining, clock.moves_to_go), (Duration::from_secs(7), Some(1)));
+        assert!(!clock.spend(Duration::from_secs(7)));
+        assert_eq!(clock.remaining, Duration::ZERO);
+    }
+}
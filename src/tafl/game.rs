@@ -0,0 +1,304 @@
+use std::time::Duration;
+use super::board::Board;
+use super::clock::{Clock, TimeBudget};
+use super::tmove::TMove;
+
+// a position occurring again with the same side to move draws the game, the rule the solver, the
+// tablebase and the proof-number search assume
+const REPETITIONS: u32 = 2;
+
+/// How a game ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    /// The side that won, 0 for the defenders.
+    Win(u8),
+    /// A position occurred again, or the game was declared drawn.
+    Draw,
+    /// The side that gave up.
+    Resigned(u8),
+    /// The side whose clock ran out.
+    Timeout(u8),
+}
+
+impl GameResult {
+    /// The side that won, `None` for a draw.
+    pub fn winner(&self) -> Option<u8> {
+        match self {
+            GameResult::Win(winner) => Some(*winner),
+            GameResult::Resigned(loser) | GameResult::Timeout(loser) => Some(1 - loser),
+            GameResult::Draw => None
+        }
+    }
+}
+
+/// A move of the game with what it changed.
+#[derive(Clone, Copy)]
+pub struct Ply {
+    pub tmove: TMove,
+    /// Squares of the pieces the move captured, laid out like `Board::occupied`.
+    pub captured: u32,
+    /// The position after the move.
+    pub board: Board,
+    /// Time the player took for the move, if it was timed.
+    pub used: Option<Duration>,
+    /// The clocks of both players after the move, defenders first.
+    pub clocks: Option<[Clock; 2]>,
+    /// The mover's clock ran out before the move was made.
+    pub out_of_time: bool,
+}
+
+/// A game from a start position: the moves played, the moves taken back that can still be redone,
+/// the clocks and the result.
+pub struct Game {
+    pub start: Board,
+    /// Clocks of both players at the start, defenders first, `None` for untimed games.
+    pub start_clocks: Option<[Clock; 2]>,
+    /// Moves played and taken back, only the first `current` of them are on the board.
+    plies: Vec<Ply>,
+    current: usize,
+    ended: Option<GameResult>,
+}
+
+impl Game {
+    pub fn new(start: Board) -> Game {
+        Game { start, start_clocks: None, plies: Vec::new(), current: 0, ended: None }
+    }
+
+    /// A game in which both players start with `clock`.
+    pub fn with_clock(start: Board, clock: Clock) -> Game {
+        Game { start_clocks: Some([clock; 2]), ..Game::new(start) }
+    }
+
+    /// The current position.
+    pub fn board(&self) -> Board {
+        match self.current {
+            0 => self.start,
+            current => self.plies[current - 1].board
+        }
+    }
+
+    /// The moves on the board, from the start.
+    pub fn plies(&self) -> &[Ply] {
+        &self.plies[..self.current]
+    }
+
+    pub fn len(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current == 0
+    }
+
+    /// The start position and the position after every move on the board.
+    pub fn positions(&self) -> impl Iterator<Item = Board> + '_ {
+        std::iter::once(self.start).chain(self.plies().iter().map(|ply| ply.board))
+    }
+
+    /// The clocks of both players now, defenders first.
+    pub fn clocks(&self) -> Option<[Clock; 2]> {
+        match self.current {
+            0 => self.start_clocks,
+            current => self.plies[current - 1].clocks
+        }
+    }
+
+    /// What the side to move may spend on its move.
+    pub fn budget(&self) -> Option<TimeBudget> {
+        self.clocks().map(|clocks| clocks[self.board().get_player() as usize].budget())
+    }
+
+    /// Number of pieces of `side` captured so far, 0 for the defenders, whose king counts as well.
+    pub fn captured(&self, side: u8) -> u32 {
+        let pieces = |board: &Board| match side {
+            0 => board.defenders(),
+            _ => board.attackers()
+        };
+        let before = self.positions();
+        self.plies().iter().zip(before).map(|(ply, before)| (ply.captured & pieces(&before)).count_ones()).sum()
+    }
+
+    /// How often the current position occurred so far with the same side to move, itself included.
+    pub fn repetitions(&self) -> u32 {
+        let board = self.board();
+        self.positions().filter(|position| position.board == board.board).count() as u32
+    }
+
+    /// Whether playing `tmove` would draw the game by repeating a position.
+    pub fn repeats(&self, tmove: &TMove) -> bool {
+        let next = self.board().make_move(tmove);
+        self.positions().filter(|position| position.board == next.board).count() as u32 + 1 >= REPETITIONS
+    }
+
+    /// The result once the game is over: a side ran out of time, won by the rules or by resignation,
+    /// a side to move without moves lost, or the current position occurred before.
+    pub fn result(&self) -> Option<GameResult> {
+        if self.ended.is_some() { return self.ended; }
+        let board = self.board();
+        if self.plies().last().is_some_and(|ply| ply.out_of_time) {
+            return Some(GameResult::Timeout(1 - board.get_player()));
+        }
+        if let Some(winner) = board.get_winner() {
+            return Some(GameResult::Win(winner));
+        }
+        if board.moves().is_empty() {
+            return Some(GameResult::Win(1 - board.get_player()));
+        }
+        match self.repetitions() >= REPETITIONS {
+            true => Some(GameResult::Draw),
+            false => None
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result().is_some()
+    }
+
+    /// Plays `tmove` for the side to move, discarding the moves that could be redone.
+    pub fn play(&mut self, tmove: &TMove) -> Result<(), String> {
+        self.play_timed(tmove, None)
+    }
+
+    /// Plays `tmove` and takes `used` off the mover's clock. The mover loses if that was more than
+    /// was left.
+    pub fn play_timed(&mut self, tmove: &TMove, used: Option<Duration>) -> Result<(), String> {
+        if self.is_over() { return Err("the game is over".to_string()); }
+        let board = self.board();
+        if !board.moves().iter().any(|possible| possible == tmove) {
+            return Err(format!("{tmove} is not possible"));
+        }
+        let next = board.make_move(tmove);
+        let moved = 1 << (24 - (5 * tmove.start.1 + tmove.start.0));
+        let captured = board.occupied() & !next.occupied() & !moved;
+        let mut clocks = self.clocks();
+        let mut out_of_time = false;
+        if let (Some(clocks), Some(used)) = (&mut clocks, used) {
+            out_of_time = !clocks[board.get_player() as usize].spend(used);
+        }
+        self.plies.truncate(self.current);
+        self.plies.push(Ply { tmove: *tmove, captured, board: next, used, clocks, out_of_time });
+        self.current += 1;
+        Ok(())
+    }
+
+    /// Takes back the last move, it can be redone until another move is played.
+    pub fn undo(&mut self) -> Option<TMove> {
+        if self.current == 0 { return None; }
+        self.current -= 1;
+        self.ended = None;
+        Some(self.plies[self.current].tmove)
+    }
+
+    /// Plays the last move taken back again, with the time it took.
+    pub fn redo(&mut self) -> Option<TMove> {
+        if self.current == self.plies.len() || self.ended.is_some() { return None; }
+        self.current += 1;
+        Some(self.plies[self.current - 1].tmove)
+    }
+
+    /// Ends the game with the side to move giving up.
+    pub fn resign(&mut self) {
+        if !self.is_over() {
+            self.ended = Some(GameResult::Resigned(self.board().get_player()));
+        }
+    }
+
+    /// Ends the game in a draw, by agreement or because it ran too long.
+    pub fn declare_draw(&mut self) {
+        if !self.is_over() {
+            self.ended = Some(GameResult::Draw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use super::super::testing::{START};
+
+    fn play(game: &mut Game, moves: &[&str]) {
+        for tmove in moves {
+            game.play(&TMove::from_str(tmove).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let start = START;
+        let clock = Clock { remaining: Duration::from_secs(60), increment: Duration::from_secs(1), moves_to_go: None };
+        let mut game = Game::with_clock(start, clock);
+        let first = game.board().moves()[0];
+        game.play_timed(&first, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(game.clocks().unwrap()[0].remaining, Duration::from_secs(56));
+        let second = game.board().moves()[0];
+        game.play(&second).unwrap();
+        assert!(game.play(&second).is_err());
+
+        assert_eq!(game.undo(), Some(second));
+        assert_eq!(game.undo(), Some(first));
+        assert_eq!(game.undo(), None);
+        assert_eq!(game.board().board, start.board);
+        assert_eq!(game.clocks().unwrap()[0].remaining, Duration::from_secs(60));
+        assert_eq!(game.redo(), Some(first));
+        assert_eq!(game.clocks().unwrap()[0].remaining, Duration::from_secs(56));
+
+        // a new move discards the moves that could be redone
+        let other = game.board().moves()[1];
+        game.play(&other).unwrap();
+        assert_eq!(game.redo(), None);
+        assert_eq!(game.positions().count(), 3);
+        assert_eq!(game.plies().iter().map(|ply| ply.tmove).collect::<Vec<TMove>>(), vec![first, other]);
+    }
+
+    #[test]
+    fn test_captures_and_results() {
+        let start = START;
+        // captures whenever possible, otherwise the first move
+        let mut game = Game::new(start);
+        while !game.is_over() && game.len() < 100 {
+            let moves = game.board().moves();
+            let tmove = moves.iter().find(|tmove| game.board().is_capture(tmove)).unwrap_or(&moves[0]);
+            game.play(tmove).unwrap();
+        }
+        assert!(game.captured(0) + game.captured(1) > 0);
+        assert_eq!(game.captured(0) + game.captured(1), game.plies().iter().map(|ply| ply.captured.count_ones()).sum());
+
+        // shuffling back and forth repeats the position
+        let mut game = Game::new(start);
+        let tmoves = ["b3-b4", "a1-b1", "b4-b3", "b1-a1"];
+        play(&mut game, &tmoves[..3]);
+        assert!(game.repeats(&TMove::from_str("b1-a1").unwrap()));
+        play(&mut game, &tmoves[3..]);
+        assert_eq!(game.repetitions(), 2);
+        assert_eq!(game.result(), Some(GameResult::Draw));
+        assert!(game.play(&TMove::from_str("b3-b4").unwrap()).is_err());
+        game.undo();
+        assert_eq!(game.result(), None);
+        game.resign();
+        assert_eq!(game.result().and_then(|result| result.winner()), Some(1 - game.board().get_player()));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = START;
+        let clock = Clock { remaining: Duration::from_secs(10), increment: Duration::from_secs(1), moves_to_go: None };
+        let mut game = Game::with_clock(start, clock);
+        let first = game.board().moves()[0];
+        game.play_timed(&first, Some(Duration::from_secs(4))).unwrap();
+        assert_eq!(game.result(), None);
+        let reply = game.board().moves()[0];
+        game.play_timed(&reply, Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(game.result(), Some(GameResult::Timeout(1)));
+        assert_eq!(game.result().unwrap().winner(), Some(0));
+        assert_eq!(game.clocks().unwrap()[1].remaining, Duration::ZERO);
+        assert!(game.play(&game.board().moves()[0]).is_err());
+
+        // taking the move back gives the time back
+        game.undo();
+        assert_eq!(game.result(), None);
+        assert_eq!(game.clocks().unwrap()[1].remaining, Duration::from_secs(10));
+        game.redo();
+        assert_eq!(game.result(), Some(GameResult::Timeout(1)));
+    }
+}
@@ -0,0 +1,259 @@
+use super::board::Board;
+
+const fn binomials() -> [[u64; 26]; 26] {
+    let mut table = [[0; 26]; 26];
+    let mut n = 0;
+    while n < 26 {
+        table[n][0] = 1;
+        let mut k = 1;
+        while k <= n {
+            table[n][k] = table[n - 1][k - 1] + table[n - 1][k];
+            k += 1;
+        }
+        n += 1;
+    }
+    table
+}
+
+static BINOMIAL: [[u64; 26]; 26] = binomials();
+
+/// Square `5 * y + x` under each of the eight symmetries of the board: bit 2 transposes, bit 0
+/// mirrors the columns and bit 1 the rows.
+const fn symmetries() -> [[u8; 25]; 8] {
+    let mut table = [[0; 25]; 8];
+    let mut symmetry = 0;
+    while symmetry < 8 {
+        let mut square = 0;
+        while square < 25 {
+            let (mut x, mut y) = (square % 5, square / 5);
+            if symmetry & 4 != 0 { (x, y) = (y, x); }
+            if symmetry & 1 != 0 { x = 4 - x; }
+            if symmetry & 2 != 0 { y = 4 - y; }
+            table[symmetry][square] = (5 * y + x) as u8;
+            square += 1;
+        }
+        symmetry += 1;
+    }
+    table
+}
+
+static SYMMETRY: [[u8; 25]; 8] = symmetries();
+
+/// King squares of positions ranked modulo symmetry, every square can be mapped to one of them.
+const KING_SQUARES: [u8; 6] = [0, 1, 2, 6, 7, 12];
+
+const fn king_slots() -> [u8; 25] {
+    let mut slots = [u8::MAX; 25];
+    let mut slot = 0;
+    while slot < KING_SQUARES.len() {
+        slots[KING_SQUARES[slot] as usize] = slot as u8;
+        slot += 1;
+    }
+    slots
+}
+
+static KING_SLOT: [u8; 25] = king_slots();
+
+const ALL_SQUARES: u32 = (1 << 25) - 1;
+
+/// Material of a position besides the king, which every ranked position has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Signature {
+    pub attackers: u8,
+    pub defenders: u8,
+}
+
+impl Signature {
+    pub fn of(board: &Board) -> Option<Signature> {
+        match board.king() {
+            0 => None,
+            _ => Some(Signature { attackers: board.attackers().count_ones() as u8, defenders: board.defenders().count_ones() as u8 - 1 })
+        }
+    }
+
+    /// Positions per side to move and king square.
+    fn placements(&self) -> u64 {
+        let (a, d) = (self.attackers as usize, self.defenders as usize);
+        BINOMIAL[24][d] * BINOMIAL[24 - d][a]
+    }
+
+    /// Number of positions: side to move, king square, then the squares of the defenders among the
+    /// other 24 and of the attackers among the rest.
+    pub fn size(&self) -> u64 {
+        2 * 25 * self.placements()
+    }
+
+    /// Number of indices of `rank_symmetric`, about a quarter of `size`.
+    pub fn symmetric_size(&self) -> u64 {
+        2 * KING_SQUARES.len() as u64 * self.placements()
+    }
+}
+
+/// Rank of the squares in `set` among the squares in `free`, in the combinatorial number system.
+fn rank_subset(set: u32, free: u32) -> u64 {
+    let (mut rank, mut position, mut chosen) = (0, 0, 0);
+    for square in 0..25 {
+        let bit = 1 << (24 - square);
+        if free & bit == 0 { continue; }
+        if set & bit != 0 {
+            chosen += 1;
+            rank += BINOMIAL[position][chosen];
+        }
+        position += 1;
+    }
+    rank
+}
+
+/// Squares of the subset of `free` with `count` squares and the given rank.
+fn unrank_subset(mut rank: u64, count: usize, free: u32) -> u32 {
+    let mut squares = [0; 25];
+    let mut len = 0;
+    for square in 0..25 {
+        if free & (1 << (24 - square)) != 0 {
+            squares[len] = square;
+            len += 1;
+        }
+    }
+    let mut set = 0;
+    for chosen in (1..=count).rev() {
+        let mut position = chosen - 1;
+        while BINOMIAL[position + 1][chosen] <= rank { position += 1; }
+        rank -= BINOMIAL[position][chosen];
+        set |= 1 << (24 - squares[position]);
+    }
+    set
+}
+
+/// Index of `board` given the index of its king square among `kings` possible ones.
+fn rank_with_king(board: &Board, signature: Signature, king_index: u64, kings: u64) -> u64 {
+    let king = board.king();
+    let defenders = board.defenders() & !king;
+    let defender_rank = rank_subset(defenders, ALL_SQUARES & !king);
+    let attacker_rank = rank_subset(board.attackers(), ALL_SQUARES & !king & !defenders);
+    let (a, d) = (signature.attackers as usize, signature.defenders as usize);
+    ((board.get_player() as u64 * kings + king_index) * BINOMIAL[24][d] + defender_rank) * BINOMIAL[24 - d][a] + attacker_rank
+}
+
+/// Board of an index made by `rank_with_king`, `king_square` maps the king index to its square.
+fn unrank_with_king<F: Fn(u64) -> u64>(signature: Signature, index: u64, kings: u64, king_square: F) -> Board {
+    let (a, d) = (signature.attackers as usize, signature.defenders as usize);
+    let attacker_rank = index % BINOMIAL[24 - d][a];
+    let rest = index / BINOMIAL[24 - d][a];
+    let defender_rank = rest % BINOMIAL[24][d];
+    let rest = rest / BINOMIAL[24][d];
+    let (player, king_index) = (rest / kings, rest % kings);
+
+    let king = 1 << (24 - king_square(king_index));
+    let defenders = unrank_subset(defender_rank, d, ALL_SQUARES & !king);
+    let attackers = unrank_subset(attacker_rank, a, ALL_SQUARES & !king & !defenders);
+    Board::from_bitboards(player as u8, attackers, defenders, king)
+}
+
+/// Dense index of `board` among the `Signature::size` positions of its material. The board must
+/// have a king, positions without one are decided and need no index.
+pub fn rank(board: &Board) -> u64 {
+    let signature = Signature::of(board).expect("ranked positions have a king");
+    let king_square = 24 - board.king().trailing_zeros() as u64;
+    rank_with_king(board, signature, king_square, 25)
+}
+
+/// Position with the given index, the inverse of `rank`.
+pub fn unrank(signature: Signature, index: u64) -> Board {
+    unrank_with_king(signature, index, 25, |king_square| king_square)
+}
+
+/// `board` under one of the eight symmetries of the board, `symmetry` is in `0..8`.
+pub fn transform(board: &Board, symmetry: usize) -> Board {
+    let map = |mut bits: u32| {
+        let mut mapped = 0;
+        while bits != 0 {
+            let square = 24 - bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            mapped |= 1 << (24 - SYMMETRY[symmetry][square]);
+        }
+        mapped
+    };
+    Board::from_bitboards(board.get_player(), map(board.attackers()), map(board.defenders()), map(board.king()))
+}
+
+/// Index of `board` modulo the symmetries of the board, below `Signature::symmetric_size`. The king
+/// is mapped to one of six squares. Where several symmetries do that, because the king stands on a
+/// diagonal or a middle line, the smallest index is taken, so some indices are never used.
+pub fn rank_symmetric(board: &Board) -> u64 {
+    let signature = Signature::of(board).expect("ranked positions have a king");
+    let king_square = 24 - board.king().trailing_zeros() as usize;
+    (0..8)
+        .filter(|symmetry| KING_SLOT[SYMMETRY[*symmetry][king_square] as usize] != u8::MAX)
+        .map(|symmetry| {
+            let canonical = transform(board, symmetry);
+            let slot = KING_SLOT[SYMMETRY[symmetry][king_square] as usize];
+            rank_with_king(&canonical, signature, slot as u64, KING_SQUARES.len() as u64)
+        })
+        .min()
+        .unwrap()
+}
+
+/// A position with the given index modulo symmetry. For indices returned by `rank_symmetric` it is
+/// symmetric to the ranked board and ranks to the same index again.
+pub fn unrank_symmetric(signature: Signature, index: u64) -> Board {
+    unrank_with_king(signature, index, KING_SQUARES.len() as u64, |slot| KING_SQUARES[slot as usize] as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+    use super::super::testing::{Lcg, START};
+
+    fn signatures(max_attackers: u8, max_defenders: u8) -> Vec<Signature> {
+        (0..=max_attackers).flat_map(|attackers| (0..=max_defenders).map(move |defenders| Signature { attackers, defenders })).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for signature in signatures(2, 1) {
+            for index in 0..signature.size() {
+                let board = unrank(signature, index);
+                assert_eq!(Signature::of(&board), Some(signature));
+                assert_eq!(rank(&board), index);
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetric_round_trip() {
+        for signature in signatures(3, 0).into_iter().chain(signatures(1, 1)) {
+            let mut indices = HashSet::new();
+            let mut classes = HashSet::new();
+            for index in 0..signature.size() {
+                let board = unrank(signature, index);
+                let symmetric = rank_symmetric(&board);
+                assert!(symmetric < signature.symmetric_size());
+                let canonical = unrank_symmetric(signature, symmetric);
+                assert_eq!(rank_symmetric(&canonical), symmetric);
+                assert!((0..8).any(|symmetry| transform(&board, symmetry).board == canonical.board));
+                indices.insert(symmetric);
+                classes.insert((0..8).map(|symmetry| rank(&transform(&board, symmetry))).min().unwrap());
+            }
+            assert_eq!(indices.len(), classes.len());
+        }
+    }
+
+    #[test]
+    fn test_symmetries_preserve_rules() {
+        let mut board = START;
+        let mut random = Lcg(7);
+        while board.get_winner().is_none() && !board.moves().is_empty() {
+            for symmetry in 0..8 {
+                let transformed = transform(&board, symmetry);
+                assert_eq!(transformed.get_winner(), board.get_winner());
+                let mut children = board.moves().iter().map(|tmove| transform(&board.make_move(tmove), symmetry).board).collect::<Vec<u64>>();
+                let mut transformed_children = transformed.moves().iter().map(|tmove| transformed.make_move(tmove).board).collect::<Vec<u64>>();
+                children.sort();
+                transformed_children.sort();
+                assert_eq!(children, transformed_children);
+            }
+            board = board.make_move(&random.pick(&board.moves()));
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use super::board::Board;
+use super::score::{loss_in, win_in};
+use super::search::{DepthResult, SearchLimits};
+use super::stats::SearchStats;
+use super::tmove::TMove;
+
+const NO_NODE: u32 = u32::MAX;
+// playouts longer than this are counted as draws, random play rarely ends a game sooner
+const MAX_PLAYOUT_PLIES: u32 = 200;
+const DEFAULT_ITERATIONS: u64 = 100_000;
+// a node takes 40 bytes, so a full tree takes about 80 MB
+const DEFAULT_MAX_NODES: usize = 2_000_000;
+// the clock is read every 256 iterations
+const POLL_MASK: u64 = 255;
+// mean results map to scores within -SCORE_SCALE..=SCORE_SCALE
+const SCORE_SCALE: f64 = 1000.0;
+const EVAL_SCALE: f64 = 50.0;
+
+/// How a playout finishes a game from a new leaf.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Playout {
+    /// Uniformly random moves until the game ends.
+    Random,
+    /// The move leaving the opponent the lowest eval, or a random one with probability `epsilon`.
+    /// After `plies` moves the eval decides the result.
+    Eval { epsilon: f64, plies: u32 },
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    board: Board,
+    /// The move leading here from the parent.
+    tmove: TMove,
+    parent: u32,
+    /// Children are stored consecutively, `first_child` is `NO_NODE` until the node is expanded.
+    first_child: u32,
+    children: u16,
+    visits: u32,
+    /// Sum of the results for the side that moved into this node, 1 for a win and 0 for a loss.
+    reward: f64,
+}
+
+/// Monte Carlo tree search: repeatedly descends the tree by UCT, expands a leaf and finishes the game
+/// from there by a playout, then credits the result to every node on the way. The most visited root
+/// move is played. The tree is kept, so that the next search from a position reached from the root
+/// starts with its subtree.
+pub struct Mcts {
+    nodes: Vec<Node>,
+    /// Weight of the exploration term of UCT, the square root of two in theory.
+    pub exploration: f64,
+    pub playout: Playout,
+    /// Keep the subtree of the new position between searches.
+    pub reuse_tree: bool,
+    /// Leaves are no longer expanded once the tree has this many nodes, only played out.
+    pub max_nodes: usize,
+    pub stats: SearchStats,
+    seed: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl Mcts {
+    pub fn new() -> Mcts {
+        Mcts { nodes: Vec::new(), exploration: std::f64::consts::SQRT_2, playout: Playout::Random, reuse_tree: true, max_nodes: DEFAULT_MAX_NODES,
+            stats: SearchStats::default(), seed: 0x2545_f491_4f6c_dd1d, stop: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Nodes in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    fn random(&mut self, n: usize) -> usize {
+        // xorshift64*
+        self.seed ^= self.seed >> 12;
+        self.seed ^= self.seed << 25;
+        self.seed ^= self.seed >> 27;
+        (self.seed.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+    }
+
+    fn children(&self, index: u32) -> std::ops::Range<usize> {
+        let node = &self.nodes[index as usize];
+        node.first_child as usize..node.first_child as usize + node.children as usize
+    }
+
+    fn push(&mut self, board: Board, tmove: TMove, parent: u32) {
+        self.nodes.push(Node { board, tmove, parent, first_child: NO_NODE, children: 0, visits: 0, reward: 0.0 });
+    }
+
+    /// Makes the node with `board` the root: the old root or one of the first two plies below it if
+    /// the tree is reused, a new tree otherwise. The subtree is copied to the front, dropping
+    /// everything else. The children of a reused root are made to match `root_moves`, moves left out
+    /// by an earlier search start without a subtree.
+    fn set_root(&mut self, board: &Board, root_moves: &Option<Vec<TMove>>) {
+        let reused = match self.nodes.first() {
+            Some(_) if self.reuse_tree => {
+                let grandchildren = self.children(0).flat_map(|child| self.children(child as u32)).collect::<Vec<usize>>();
+                std::iter::once(0).chain(self.children(0)).chain(grandchildren)
+                    .find(|candidate| self.nodes[*candidate].board.board == board.board)
+            }
+            _ => None
+        };
+        let Some(root) = reused else {
+            self.nodes.clear();
+            self.push(*board, TMove { start: (0, 0), end: (0, 0) }, NO_NODE);
+            return;
+        };
+
+        let mut nodes = vec![Node { parent: NO_NODE, ..self.nodes[root] }];
+        let mut next = 0;
+        while next < nodes.len() {
+            let old = nodes[next];
+            if old.first_child != NO_NODE {
+                let first_child = nodes.len() as u32;
+                let children = old.first_child as usize..old.first_child as usize + old.children as usize;
+                if next == 0 {
+                    for tmove in board.moves().iter() {
+                        if root_moves.as_ref().is_some_and(|allowed| !allowed.contains(tmove)) { continue; }
+                        match self.nodes[children.clone()].iter().find(|child| child.tmove == *tmove) {
+                            Some(child) => nodes.push(Node { parent: 0, ..*child }),
+                            None => nodes.push(Node { board: board.make_move(tmove), tmove: *tmove, parent: 0,
+                                first_child: NO_NODE, children: 0, visits: 0, reward: 0.0 })
+                        }
+                    }
+                } else {
+                    for child in children {
+                        nodes.push(Node { parent: next as u32, ..self.nodes[child] });
+                    }
+                }
+                nodes[next].first_child = first_child;
+                nodes[next].children = (nodes.len() as u32 - first_child) as u16;
+            }
+            next += 1;
+        }
+        self.nodes = nodes;
+    }
+
+    /// Child maximizing the UCT value, unvisited children first.
+    fn select(&self, index: u32) -> u32 {
+        let log_visits = (self.nodes[index as usize].visits.max(1) as f64).ln();
+        let uct = |child: &Node| match child.visits {
+            0 => f64::INFINITY,
+            visits => child.reward / visits as f64 + self.exploration * (log_visits / visits as f64).sqrt()
+        };
+        self.children(index).max_by(|a, b| uct(&self.nodes[*a]).total_cmp(&uct(&self.nodes[*b]))).unwrap() as u32
+    }
+
+    fn expand(&mut self, index: u32, root_moves: &Option<Vec<TMove>>) {
+        let board = self.nodes[index as usize].board;
+        let first_child = self.nodes.len() as u32;
+        for tmove in board.moves().iter() {
+            if index == 0 && root_moves.as_ref().is_some_and(|allowed| !allowed.contains(tmove)) { continue; }
+            self.push(board.make_move(tmove), *tmove, index);
+        }
+        let children = (self.nodes.len() as u32 - first_child) as u16;
+        let node = &mut self.nodes[index as usize];
+        node.first_child = first_child;
+        node.children = children;
+    }
+
+    /// Result of a game from `board` for the defenders, 1 for a win, 0 for a loss and in between for
+    /// unfinished playouts.
+    fn play_out(&mut self, board: &Board) -> f64 {
+        let mut board = *board;
+        for ply in 0..MAX_PLAYOUT_PLIES {
+            if let Some(winner) = board.get_winner() {
+                return (1 - winner) as f64;
+            }
+            let moves = board.moves();
+            if moves.is_empty() {
+                return board.get_player() as f64;
+            }
+            let playout = self.playout;
+            let tmove = match playout {
+                Playout::Eval { plies, .. } if ply >= plies => {
+                    // eval is for the side to move, 50 points make a win about ten times as likely
+                    let eval = board.eval() as f64 * if board.get_player() == 0 { 1.0 } else { -1.0 };
+                    return 1.0 / (1.0 + 10f64.powf(-eval / EVAL_SCALE));
+                }
+                Playout::Eval { epsilon, .. } if (self.random(1 << 16) as f64) >= epsilon * (1 << 16) as f64 => {
+                    *moves.iter().min_by_key(|tmove| {
+                        let child = board.make_move(tmove);
+                        match child.get_winner() {
+                            Some(_) => i32::MIN,
+                            None => child.eval()
+                        }
+                    }).unwrap()
+                }
+                _ => moves[self.random(moves.len())]
+            };
+            self.stats.nodes += 1;
+            board = board.make_move(&tmove);
+        }
+        0.5
+    }
+
+    fn iterate(&mut self, root_moves: &Option<Vec<TMove>>) {
+        let mut index = 0;
+        let mut depth = 0;
+        while self.nodes[index as usize].first_child != NO_NODE && self.nodes[index as usize].children > 0 {
+            index = self.select(index);
+            depth += 1;
+        }
+        let board = self.nodes[index as usize].board;
+        if board.get_winner().is_none() && self.nodes[index as usize].first_child == NO_NODE {
+            // a leaf is expanded on its second visit, single visits are mostly noise, and not at all
+            // once the tree is full
+            if index == 0 || (self.nodes[index as usize].visits > 0 && self.nodes.len() < self.max_nodes) {
+                self.expand(index, root_moves);
+                if self.nodes[index as usize].children > 0 {
+                    index = self.select(index);
+                    depth += 1;
+                }
+            }
+        }
+        self.stats.max_selective_depth = self.stats.max_selective_depth.max(depth);
+
+        let leaf = self.nodes[index as usize].board;
+        self.stats.nodes += 1;
+        let result = self.play_out(&leaf);
+        while index != NO_NODE {
+            let node = &mut self.nodes[index as usize];
+            node.visits += 1;
+            // the side that moved into the node is the one not to move there
+            node.reward += match node.board.get_player() {
+                1 => result,
+                _ => 1.0 - result
+            };
+            index = node.parent;
+        }
+    }
+
+    /// Most visited child of a node.
+    fn most_visited(&self, index: u32) -> Option<u32> {
+        self.children(index).max_by_key(|child| self.nodes[*child].visits).map(|child| child as u32)
+    }
+
+    fn principal_variation(&self) -> Vec<TMove> {
+        let mut pv = Vec::new();
+        let mut index = 0;
+        while let Some(child) = self.most_visited(index).filter(|child| self.nodes[*child as usize].visits > 0) {
+            pv.push(self.nodes[child as usize].tmove);
+            index = child;
+        }
+        pv
+    }
+
+    /// Score of the most visited root move for the side to move: a decided score if the move wins or
+    /// loses at once, the mean result scaled to the range of the eval otherwise.
+    fn score(&self, child: u32) -> i32 {
+        let node = &self.nodes[child as usize];
+        match node.board.get_winner() {
+            Some(winner) if winner == self.nodes[0].board.get_player() => win_in(1),
+            Some(_) => loss_in(1),
+            None => ((node.reward / node.visits.max(1) as f64 * 2.0 - 1.0) * SCORE_SCALE) as i32
+        }
+    }
+
+    fn result(&self, iterations: u64, t1: Instant, snapshot: SearchStats) -> Option<DepthResult> {
+        let child = self.most_visited(0)?;
+        let pv = self.principal_variation();
+        let stats = SearchStats { nodes: self.stats.nodes - snapshot.nodes, ..self.stats };
+        Some(DepthResult { depth: pv.len() as i32, score: self.score(child), tmove: Some(self.nodes[child as usize].tmove), pv,
+            elapsed: t1.elapsed().as_secs_f32(), complete: iterations > 0, stats, branching_factor: None, lines: Vec::new() })
+    }
+
+    /// Searches `board` within the time and node budget of `limits`, the nodes counting the leaves
+    /// and the moves of the playouts. Without either limit it runs a fixed number of iterations. Unlike iterative deepening
+    /// the search can stop at any time, so it stops at the soft time limit. `on_report` is called
+    /// each time the number of iterations doubles, with the depth of the most visited line.
+    pub fn search<F>(&mut self, board: &Board, limits: &SearchLimits, mut on_report: F) -> Option<DepthResult>
+    where F: FnMut(&Mcts, &DepthResult) {
+        let t0 = Instant::now();
+        self.stats = SearchStats::default();
+        self.set_root(board, &limits.root_moves);
+        if board.get_winner().is_some() { return None; }
+        let deadline = limits.time.map(|time| t0 + time.soft);
+        let max_iterations = match (limits.time, limits.nodes) {
+            (None, None) => Some(DEFAULT_ITERATIONS),
+            _ => None
+        };
+
+        let mut iterations = 0u64;
+        let mut next_report = 1024;
+        let mut t1 = Instant::now();
+        let mut snapshot = self.stats;
+        while !self.is_stopped() {
+            self.iterate(&limits.root_moves);
+            iterations += 1;
+            self.stats.normal_calls += 1;
+            if iterations == next_report {
+                if let Some(result) = self.result(iterations, t1, snapshot) {
+                    on_report(self, &result);
+                }
+                next_report *= 2;
+                t1 = Instant::now();
+                snapshot = self.stats;
+            }
+            if iterations & POLL_MASK == 0 {
+                let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                let out_of_nodes = limits.nodes.is_some_and(|limit| self.stats.nodes >= limit);
+                if out_of_time || out_of_nodes { break; }
+            }
+            if max_iterations.is_some_and(|max| iterations >= max) { break; }
+            if self.nodes[0].children == 0 { break; }
+        }
+        let result = self.result(iterations, t1, snapshot);
+        if let Some(result) = &result {
+            on_report(self, result);
+        }
+        result
+    }
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Mcts::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{START};
+
+    #[test]
+    fn test_finds_escape() {
+        // the king on c3 escapes along its row or column
+        let board = Board { board: 0b0_0100000001_0000000000_0000110000_0001000100_0000000000 };
+        let mut mcts = Mcts::new();
+        let result = mcts.search(&board, &SearchLimits { nodes: Some(100_000), ..SearchLimits::default() }, |_, _| {}).unwrap();
+        assert_eq!(board.make_move(&result.tmove.unwrap()).get_winner(), Some(0));
+        assert_eq!(result.score, win_in(1));
+    }
+
+    #[test]
+    fn test_reuses_tree() {
+        let board = START;
+        let mut mcts = Mcts::new();
+        let result = mcts.search(&board, &SearchLimits { nodes: Some(200_000), ..SearchLimits::default() }, |_, _| {}).unwrap();
+        let pv = result.pv;
+        let child = board.make_move(&pv[0]);
+        let grandchild = child.make_move(&pv[1]);
+        let visits = mcts.nodes[mcts.most_visited(0).unwrap() as usize].visits;
+
+        // a tiny budget still runs a few hundred iterations before the budget is checked
+        let small = SearchLimits { nodes: Some(1), ..SearchLimits::default() };
+        mcts.search(&child, &small, |_, _| {});
+        assert!(mcts.nodes[0].visits > visits);
+        mcts.reuse_tree = false;
+        mcts.search(&grandchild, &small, |_, _| {});
+        assert!(mcts.nodes[0].visits <= 1024);
+    }
+
+    #[test]
+    fn test_eval_playouts_capture_king() {
+        // the attackers capture the king on c3 with d5-d3
+        let board = Board { board: 0b1_0000000100_0000000000_0001110000_0000000000_0000000000 };
+        let mut mcts = Mcts::new();
+        mcts.playout = Playout::Eval { epsilon: 0.2, plies: 8 };
+        let result = mcts.search(&board, &SearchLimits { nodes: Some(50_000), ..SearchLimits::default() }, |_, _| {}).unwrap();
+        assert_eq!(board.make_move(&result.tmove.unwrap()).get_winner(), Some(1));
+    }
+
+    #[test]
+    fn test_node_cap() {
+        let board = START;
+        let mut mcts = Mcts::new();
+        mcts.max_nodes = 1000;
+        let result = mcts.search(&board, &SearchLimits { nodes: Some(100_000), ..SearchLimits::default() }, |_, _| {});
+        assert!(board.moves().contains(&result.unwrap().tmove.unwrap()));
+        // the last expansion may overshoot by the moves of one position
+        assert!(mcts.len() < 1000 + board.moves().len() * 4);
+        assert!(mcts.nodes[0].visits > 2000);
+    }
+
+    #[test]
+    fn test_reused_root_keeps_root_moves() {
+        let board = START;
+        let mut mcts = Mcts::new();
+        let limits = SearchLimits { nodes: Some(50_000), ..SearchLimits::default() };
+        let best = mcts.search(&board, &limits, |_, _| {}).unwrap().tmove.unwrap();
+        let visits = mcts.nodes[0].visits;
+
+        let allowed = *board.moves().iter().find(|tmove| **tmove != best).unwrap();
+        let restricted = SearchLimits { root_moves: Some(vec![allowed]), ..limits.clone() };
+        assert_eq!(mcts.search(&board, &restricted, |_, _| {}).unwrap().tmove, Some(allowed));
+        assert!(mcts.nodes[0].visits > visits);
+        assert_eq!(mcts.nodes[0].children, 1);
+
+        // the moves left out before are searched again
+        mcts.search(&board, &limits, |_, _| {});
+        assert_eq!(mcts.nodes[0].children as usize, board.moves().len());
+        assert!(mcts.children(0).all(|child| mcts.nodes[child].visits > 0));
+    }
+}
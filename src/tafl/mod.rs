@@ -1,3 +1,21 @@
 pub mod board;
+pub mod clock;
+pub mod game;
+pub mod index;
+pub mod mcts;
 pub mod negamax;
-pub mod tmove;
\ No newline at end of file
+pub mod perft;
+pub mod picker;
+pub mod player;
+pub mod pns;
+pub mod proof;
+pub mod retro;
+pub mod score;
+pub mod search;
+pub mod solver;
+pub mod stats;
+pub mod tablebase;
+#[cfg(test)]
+mod testing;
+pub mod tmove;
+pub mod ttable;
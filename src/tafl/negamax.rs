@@ -1,161 +1,725 @@
-use metrohash::MetroHashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
 use super::board::Board;
-use super::tmove::TMove;
+use super::score::{is_decided, loss_in, win_in, INFINITY};
+use super::picker::{MovePicker, Stage};
+use super::solver::Value;
+use super::tmove::{MoveList, TMove};
+use super::stats::SearchStats;
+use super::tablebase::Tablebase;
+use super::ttable::{TTEntry, TranspositionTable};
 
-#[derive(Clone, Copy)]
-pub struct TTEntry {
-    pub tmove: TMove
+const MAX_PLY: usize = 64;
+const HISTORY_MAX: i32 = 1 << 20;
+const QUIESCENCE_DEPTH: i32 = 6;
+const NULL_MOVE_REDUCTION: i32 = 2;
+const LMR_MIN_MOVES: usize = 3;
+const ASPIRATION_WINDOW: i32 = 8;
+// the clock is read every 1024 nodes
+const POLL_MASK: u64 = 1023;
+
+/// Score of a decided position at ply `d` for its side to move.
+#[inline]
+fn decided_score(board: &Board, d: i32) -> Option<i32> {
+    board.get_winner().map(|winner| match winner == board.get_player() {
+        true => win_in(d + 1),
+        false => loss_in(d + 1)
+    })
+}
+
+#[inline]
+fn square(pos: (u8, u8)) -> usize {
+    pos.1 as usize * 5 + pos.0 as usize
 }
 
 pub struct  Negamax {
-    pub map: MetroHashMap<u64, TTEntry>,
-    pub zero_window_calls: i32,
-    pub pvs_failed_calls: i32,
-    pub transpo_calls: i32,
-    pub normal_calls: i32,
+    pub map: Arc<TranspositionTable>,
+    pub killers: [[Option<TMove>; 2]; MAX_PLY],
+    pub history: [[i32; 25]; 25],
+    pub stats: SearchStats,
+    pub null_move: bool,
+    pub lmr: bool,
+    /// Order quiet moves by killer moves and the history table, otherwise in the order of the move generator.
+    pub move_ordering: bool,
+    pub root_moves: Option<Vec<TMove>>,
+    pub threads: usize,
+    /// Exact values of positions with little material, probed below the root.
+    pub tablebase: Option<Arc<Tablebase>>,
+    helper_id: usize,
+    /// Best root move of the current iteration whose search finished before the search was stopped.
+    pub root_best: Option<(i32, TMove)>,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    stop: Arc<AtomicBool>,
+    in_null_move: bool,
 }
 
 impl Negamax {
 
+    /// Picks the moves of a node lazily, skipping the transposition table move that was searched already.
+    fn move_picker(&self, board: &Board, mut moves: MoveList, d: i32, skip: Option<TMove>) -> MovePicker {
+        moves.retain(|tmove| self.is_root_move_allowed(d, tmove));
+        // plies beyond the killer table have no killers
+        let killers = self.killers.get((d + 1) as usize).copied().unwrap_or([None; 2]);
+        let mut picker = MovePicker::new(moves, killers, skip);
+        // helper threads start with different root moves so that they fill different parts of the table
+        if d < 0 && self.helper_id > 0 {
+            picker.rotate(board, &self.history, self.helper_id);
+        }
+        picker
+    }
+
+    /// Remembers a quiet move that caused a beta cutoff in the killer slots and the history table.
+    /// Cutoffs by the scores of aborted subtrees are ignored.
+    fn store_cutoff(&mut self, board: &Board, tmove: TMove, d: i32, max_d: i32, first: bool) {
+        if self.is_stopped() { return; }
+        self.stats.beta_cutoffs += 1;
+        self.stats.first_move_cutoffs += first as u64;
+        if !self.move_ordering || board.is_capture(&tmove) { return; }
+
+        if let Some(killers) = self.killers.get_mut((d + 1) as usize) {
+            if killers[0] != Some(tmove) {
+                killers[1] = killers[0];
+                killers[0] = Some(tmove);
+            }
+        }
+
+        let remaining = max_d - d;
+        let entry = &mut self.history[square(tmove.start)][square(tmove.end)];
+        *entry += remaining * remaining;
+        if *entry > HISTORY_MAX {
+            self.history.iter_mut().flatten().for_each(|h| *h /= 2);
+        }
+    }
+
+    /// Null moves are skipped at the root, in PV nodes, below another null move, when the attackers face an
+    /// escape threat and when the side to move has so few pieces that zugzwang is likely. Only zero windows
+    /// count as non-PV nodes, so plain negamax, whose windows rarely narrow that far, tries far fewer
+    /// null moves than PVS.
+    fn can_null_move(&self, board: &Board, d: i32, max_d: i32, alpha: i32, beta: i32) -> bool {
+        let player = board.get_player();
+        self.null_move && !self.in_null_move && d >= 0 && beta - alpha == 1
+            && max_d - d >= NULL_MOVE_REDUCTION + 2
+            && board.count_pieces(player) > 2
+            && !(player == 1 && board.count_king_escapes() > 0)
+            && board.eval() >= beta
+    }
+
+    /// Late quiet moves are searched one ply shallower unless they give or answer an escape threat.
+    fn can_reduce(&self, moved_board: &Board, searched: usize, stage: Stage, remaining: i32, threatened: bool) -> bool {
+        self.lmr && searched >= LMR_MIN_MOVES && stage == Stage::Quiets && remaining >= 3
+            && !threatened && moved_board.count_king_escapes() == 0
+    }
+
+    /// At the root only the moves in `root_moves` are searched, if it is set.
+    fn is_root_move_allowed(&self, d: i32, tmove: &TMove) -> bool {
+        match &self.root_moves {
+            Some(root_moves) => d >= 0 || root_moves.contains(tmove),
+            None => true
+        }
+    }
+
+    /// Counts the node and checks the deadline and node budget every few nodes, stopping the search
+    /// once one of them is reached.
+    #[inline]
+    fn poll_stop(&mut self, d: i32) -> bool {
+        self.stats.nodes += 1;
+        self.stats.max_selective_depth = self.stats.max_selective_depth.max(d + 1);
+        if self.stats.nodes & POLL_MASK == 0 {
+            let out_of_time = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let out_of_nodes = self.node_limit.is_some_and(|limit| self.stats.nodes >= limit);
+            if out_of_time || out_of_nodes { self.stop(); }
+        }
+        self.is_stopped()
+    }
+
+    fn probe(&mut self, board: &Board, d: i32) -> Option<TTEntry> {
+        self.stats.tt_probes += 1;
+        let entry = self.map.get(board.board);
+        self.stats.tt_hits += entry.is_some() as u64;
+        entry.filter(|tentry| self.is_root_move_allowed(d, &tentry.tmove))
+    }
+
+    /// Stores the best move of a node, unless the search was stopped and the move may be made up.
+    fn store(&mut self, board: &Board, tmove: TMove) {
+        if self.is_stopped() { return; }
+        self.stats.tt_stores += 1;
+        self.map.insert(board.board, TTEntry { tmove });
+    }
+
+    fn record_root_move(&mut self, d: i32, value: i32, alpha: i32, tmove: TMove) {
+        if d < 0 && value > alpha && !self.is_stopped() {
+            self.root_best = Some((value, tmove));
+        }
+    }
+
+    /// Exact score of a position below the root from the tablebase, if its material was generated.
+    fn probe_tablebase(&mut self, board: &Board, d: i32) -> Option<i32> {
+        if d < 0 { return None; }
+        let score = match self.tablebase.as_ref()?.probe(board)? {
+            Value::Win(Some(plies)) => win_in(d + 1 + plies as i32),
+            Value::Loss(Some(plies)) => loss_in(d + 1 + plies as i32),
+            _ => 0
+        };
+        self.stats.tb_hits += 1;
+        Some(score)
+    }
+
+    /// Extends the search at the horizon with captures and king escape threats until the position is quiet.
+    pub fn quiescence(&mut self, board: &Board, d: i32, qd: i32, alpha: i32, beta: i32) -> i32 {
+        if self.poll_stop(d) {
+            return alpha;
+        }
+
+        if let Some(score) = decided_score(board, d) {
+            return score;
+        }
+        if let Some(score) = self.probe_tablebase(board, d) {
+            return score;
+        }
+
+        let escapes = board.count_king_escapes();
+        let defending = board.get_player() == 0;
+
+        // the king walks to the edge with the next move
+        if defending && escapes > 0 { return win_in(d + 2); }
+
+        // the attackers have to answer an escape threat, standing pat is not an option
+        let threatened = !defending && escapes > 0;
+        let mut max = alpha;
+        if !threatened || qd == 0 {
+            let stand_pat = board.eval();
+            if stand_pat >= beta || qd == 0 { return stand_pat; }
+            if stand_pat > max { max = stand_pat; }
+        }
+
+        let possible_moves = board.moves();
+        if possible_moves.is_empty() { return loss_in(d + 1); }
+
+        let king_pos = board.get_king_pos();
+        for tmove in possible_moves.iter() {
+            let moved_board = board.make_move(tmove);
+            let noisy = threatened
+                || board.is_capture(tmove)
+                || (Some(tmove.start) == king_pos && moved_board.count_king_escapes() > 0);
+            if !noisy { continue; }
+
+            self.stats.quiescence_calls += 1;
+            let value = -self.quiescence(&moved_board, d + 1, qd - 1, -beta, -max);
+            if value > max {
+                max = value;
+                if max >= beta { break; }
+            }
+        }
+        max
+    }
+
     pub fn negamax(&mut self, board: &Board, d: i32, max_d: i32, alpha: i32, beta: i32) -> (i32, Option<TMove>) {
-        match board.get_winner() {
-            Some(0) => { return ((1000 - d) * (1 - 2 * board.get_player() as i32), None); },
-            Some(_) => { return ((1000 - d) * (2 * board.get_player() as i32 - 1), None); },
-            None => { }
+        if self.poll_stop(d) {
+            return (alpha, None);
+        }
+
+        if let Some(score) = decided_score(board, d) {
+            return (score, None);
+        }
+        if let Some(score) = self.probe_tablebase(board, d) {
+            return (score, None);
+        }
+
+        // no line from here ends sooner than this node or the next move, so the window can shrink
+        // to those bounds, and closes when a shorter win is already known
+        let (alpha, beta) = match d >= 0 {
+            true => (alpha.max(loss_in(d + 1)), beta.min(win_in(d + 2))),
+            false => (alpha, beta)
+        };
+        if alpha >= beta {
+            self.stats.mate_distance_prunes += 1;
+            return (alpha, None);
+        }
+
+        if d >= max_d {
+            return (self.quiescence(board, d, QUIESCENCE_DEPTH, alpha, beta), None);
         }
 
-        if d == max_d {
-            return (board.eval(), None);
+        if self.can_null_move(board, d, max_d, alpha, beta) {
+            self.stats.null_move_calls += 1;
+            self.in_null_move = true;
+            let value = -self.negamax(&board.next_player(), d + 1 + NULL_MOVE_REDUCTION, max_d, -beta, -beta + 1).0;
+            self.in_null_move = false;
+            if value >= beta { return (beta, None); }
         }
 
-        let entry = self.map.get(&board.board);
+        let entry = self.probe(board, d);
         let mut first_attempt = None;
         let mut max = alpha;
         let mut best_move = None;
 
-        match entry {
-            Some(tentry) => {
-                first_attempt = Some(tentry.tmove);
-                best_move = first_attempt;
-                self.transpo_calls += 1;
-                max = -self.negamax(&board.make_move(&tentry.tmove), d + 1, max_d, -beta, -alpha).0;
-            },
-            None => {}
+        if let Some(tentry) = entry {
+            first_attempt = Some(tentry.tmove);
+            best_move = first_attempt;
+            self.stats.transpo_calls += 1;
+            max = -self.negamax(&board.make_move(&tentry.tmove), d + 1, max_d, -beta, -alpha).0;
+            self.record_root_move(d, max, alpha, tentry.tmove);
+            if max >= beta { self.store_cutoff(board, tentry.tmove, d, max_d, true); }
         }
 
         if max < beta {
             // get possible moves
-            let possible_moves = board.get_possible_moves();
+            let possible_moves = board.moves();
 
             // loose condition
-            if possible_moves.is_empty() { return (-1000 + d, first_attempt); }
-
-            // sort possible moves
-            let mut moved_boards = possible_moves.into_iter().map(|tmove| (board.make_move(&tmove), tmove)).collect::<Vec<(Board, TMove)>>();
-            moved_boards.sort_by_key(|b| {
-                b.0.eval()
-            });
-
-            for (moved_board, tmove) in &moved_boards {
-                if first_attempt.is_some() && first_attempt.unwrap() == *tmove { continue; }
-                self.normal_calls += 1;
-                let value = -self.negamax(moved_board, d + 1, max_d, -beta, -max).0;
+            if possible_moves.is_empty() { return (loss_in(d + 1), first_attempt); }
+
+            let mut picker = self.move_picker(board, possible_moves, d, first_attempt);
+            let threatened = board.get_player() == 1 && board.count_king_escapes() > 0;
+
+            let mut searched = 0;
+            while let Some((tmove, stage)) = picker.next(board, &self.history) {
+                let moved_board = &board.make_move(&tmove);
+                searched += 1;
+                let mut value = max + 1;
+                if self.can_reduce(moved_board, searched - 1, stage, max_d - d, threatened) {
+                    self.stats.reduced_calls += 1;
+                    value = -self.negamax(moved_board, d + 2, max_d, -beta, -max).0;
+                }
+                if value > max {
+                    self.stats.normal_calls += 1;
+                    value = -self.negamax(moved_board, d + 1, max_d, -beta, -max).0;
+                }
                 if value > max {
                     max = value;
-                    best_move = Some(*tmove);
+                    best_move = Some(tmove);
+                    self.record_root_move(d, value, alpha, tmove);
 
-                    if max >= beta { break; }
+                    if max >= beta {
+                        self.store_cutoff(board, tmove, d, max_d, first_attempt.is_none() && searched == 1);
+                        break;
+                    }
                 }
             }
-            match best_move {
-                Some(tmove) => { self.map.insert(board.board, TTEntry { tmove }); }
-                None => {}
-            }
+            if let Some(tmove) = best_move { self.store(board, tmove); }
         } 
-        return (max, best_move);
+        (max, best_move)
     }
 
     pub fn pvs(&mut self, board: &Board, d: i32, max_d: i32, alpha: i32, beta: i32) -> (i32, Option<TMove>) {
-        match board.get_winner() {
-            Some(0) => { return ((1000 - d) * (1 - 2 * board.get_player() as i32), None); },
-            Some(_) => { return ((1000 - d) * (2 * board.get_player() as i32 - 1), None); },
-            None => { }
+        if self.poll_stop(d) {
+            return (alpha, None);
+        }
+
+        if let Some(score) = decided_score(board, d) {
+            return (score, None);
+        }
+        if let Some(score) = self.probe_tablebase(board, d) {
+            return (score, None);
         }
 
-        if d == max_d {
-            return (board.eval(), None);
+        // no line from here ends sooner than this node or the next move, so the window can shrink
+        // to those bounds, and closes when a shorter win is already known
+        let (alpha, beta) = match d >= 0 {
+            true => (alpha.max(loss_in(d + 1)), beta.min(win_in(d + 2))),
+            false => (alpha, beta)
+        };
+        if alpha >= beta {
+            self.stats.mate_distance_prunes += 1;
+            return (alpha, None);
         }
 
-        let entry = self.map.get(&board.board);
+        if d >= max_d {
+            return (self.quiescence(board, d, QUIESCENCE_DEPTH, alpha, beta), None);
+        }
+
+        if self.can_null_move(board, d, max_d, alpha, beta) {
+            self.stats.null_move_calls += 1;
+            self.in_null_move = true;
+            let value = -self.pvs(&board.next_player(), d + 1 + NULL_MOVE_REDUCTION, max_d, -beta, -beta + 1).0;
+            self.in_null_move = false;
+            if value >= beta { return (beta, None); }
+        }
+
+        let entry = self.probe(board, d);
         let mut first_attempt = None;
         let mut max = alpha;
         let mut best_move = None;
         let mut first_child_searched = false;
 
-        match entry {
-            Some(tentry) => {
-                first_attempt = Some(tentry.tmove);
-                best_move = first_attempt;
-                first_child_searched = true;
-                self.transpo_calls += 1;
-                max = -self.pvs(&board.make_move(&tentry.tmove), d + 1, max_d, -beta, -alpha).0;
-            },
-            None => {}
+        if let Some(tentry) = entry {
+            first_attempt = Some(tentry.tmove);
+            best_move = first_attempt;
+            first_child_searched = true;
+            self.stats.transpo_calls += 1;
+            max = -self.pvs(&board.make_move(&tentry.tmove), d + 1, max_d, -beta, -alpha).0;
+            self.record_root_move(d, max, alpha, tentry.tmove);
+            if max >= beta { self.store_cutoff(board, tentry.tmove, d, max_d, true); }
         }
 
         if max < beta {
             // get possible moves
-            let possible_moves = board.get_possible_moves();
+            let possible_moves = board.moves();
 
             // loose condition
-            if possible_moves.is_empty() { return (-1000 + d, first_attempt); }
+            if possible_moves.is_empty() { return (loss_in(d + 1), first_attempt); }
 
-            // sort possible moves
-            let mut moved_boards = possible_moves.into_iter().map(|tmove| (board.make_move(&tmove), tmove)).collect::<Vec<(Board, TMove)>>();
-            moved_boards.sort_by_key(|b| {
-                b.0.eval()
-            });
+            let mut picker = self.move_picker(board, possible_moves, d, first_attempt);
+            let threatened = board.get_player() == 1 && board.count_king_escapes() > 0;
 
-            for (moved_board, tmove) in &moved_boards {
-                if first_attempt.is_some() && first_attempt.unwrap() == *tmove { continue; }
-                let value = match first_child_searched.clone() {
+            let mut searched = 0;
+            while let Some((tmove, stage)) = picker.next(board, &self.history) {
+                let moved_board = &board.make_move(&tmove);
+                searched += 1;
+                let value = match first_child_searched {
                     true => {
-                        self.zero_window_calls += 1;
+                        if self.can_reduce(moved_board, searched - 1, stage, max_d - d, threatened) {
+                            self.stats.reduced_calls += 1;
+                            let score = -self.pvs(moved_board, d + 2, max_d, -max - 1, -max).0;
+                            if score <= max { continue; }
+                        }
+                        self.stats.zero_window_calls += 1;
                         let score = -self.pvs(moved_board, d + 1, max_d, -max - 1, -max).0;
-                        if max < score && score < beta { self.pvs_failed_calls += 1; -self.pvs(moved_board, d + 1, max_d, -beta, -max).0 } 
+                        if max < score && score < beta { self.stats.pvs_failed_calls += 1; -self.pvs(moved_board, d + 1, max_d, -beta, -max).0 } 
                         else { score }
                     }
                     false => {
                         first_child_searched = true;
-                        self.normal_calls += 1;
+                        self.stats.normal_calls += 1;
                         -self.pvs(moved_board, d + 1, max_d, -beta, -max).0
                     }
                 };
                 if value > max {
                     max = value;
-                    best_move = Some(*tmove);
+                    best_move = Some(tmove);
+                    self.record_root_move(d, value, alpha, tmove);
 
-                    if max >= beta { break; }
+                    if max >= beta {
+                        self.store_cutoff(board, tmove, d, max_d, first_attempt.is_none() && searched == 1);
+                        break;
+                    }
                 }
             }
-            match best_move {
-                Some(tmove) => { self.map.insert(board.board, TTEntry { tmove }); }
-                None => {}
-            }
+            if let Some(tmove) = best_move { self.store(board, tmove); }
         } 
-        return (max, best_move);
+        (max, best_move)
     }
 
     pub fn solve(&mut self, board: &Board, depth: i32, pvs: bool) -> (i32, Option<TMove>) {
+        self.solve_window(board, depth, pvs, -INFINITY, INFINITY)
+    }
+
+    pub fn solve_window(&mut self, board: &Board, depth: i32, pvs: bool, alpha: i32, beta: i32) -> (i32, Option<TMove>) {
+        self.root_best = None;
+        if self.threads > 1 {
+            return self.solve_parallel(board, depth, pvs, alpha, beta);
+        }
         if pvs {
-            self.pvs(board, -1, depth, -10_000, 10_000)
+            self.pvs(board, -1, depth, alpha, beta)
         } else {
-            self.negamax(board, -1, depth, -10_000, 10_000)
+            self.negamax(board, -1, depth, alpha, beta)
+        }
+    }
+
+    /// Searches with a narrow window around `guess`, the score of the previous iteration, and widens the
+    /// failing side of the window until the score lies inside it. Decided scores are searched with a full window.
+    pub fn solve_aspiration(&mut self, board: &Board, depth: i32, pvs: bool, guess: i32) -> (i32, Option<TMove>) {
+        if is_decided(guess) {
+            return self.solve(board, depth, pvs);
+        }
+
+        let mut delta = ASPIRATION_WINDOW;
+        let mut alpha = guess - delta;
+        let mut beta = guess + delta;
+        loop {
+            let result = self.solve_window(board, depth, pvs, alpha, beta);
+            if self.is_stopped() {
+                return result;
+            } else if result.0 <= alpha && alpha > -INFINITY {
+                alpha = (guess - 2 * delta).max(-INFINITY);
+            } else if result.0 >= beta && beta < INFINITY {
+                beta = (guess + 2 * delta).min(INFINITY);
+            } else {
+                return result;
+            }
+            self.stats.aspiration_researches += 1;
+            delta *= 2;
+        }
+    }
+
+    /// Lazy SMP: helper threads search the same position with their own killer and history tables,
+    /// alternating depths and rotated root moves, and share their results through the transposition
+    /// table. The result is the one of the calling thread, helpers are stopped as soon as it finishes
+    /// and the root entry they may have overwritten is stored again.
+    fn solve_parallel(&mut self, board: &Board, depth: i32, pvs: bool, alpha: i32, beta: i32) -> (i32, Option<TMove>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut helpers = (1..self.threads).map(|helper_id| {
+            let mut helper = Negamax::with_table(self.map.clone());
+            helper.null_move = self.null_move;
+            helper.lmr = self.lmr;
+            helper.move_ordering = self.move_ordering;
+            helper.root_moves = self.root_moves.clone();
+            helper.tablebase = self.tablebase.clone();
+            helper.helper_id = helper_id;
+            helper.stop = stop.clone();
+            helper
+        }).collect::<Vec<Negamax>>();
+
+        let threads = self.threads;
+        self.threads = 1;
+        let result = thread::scope(|scope| {
+            for helper in helpers.iter_mut() {
+                let helper_depth = depth + (helper.helper_id % 2) as i32;
+                scope.spawn(move || helper.solve_window(board, helper_depth, pvs, alpha, beta));
+            }
+            let result = self.solve_window(board, depth, pvs, alpha, beta);
+            stop.store(true, Ordering::Relaxed);
+            result
+        });
+        self.threads = threads;
+        if let Some(tmove) = result.1 {
+            self.store(board, tmove);
+        }
+
+        for helper in &helpers {
+            self.stats += helper.stats;
+        }
+        result
+    }
+
+    /// Asks a running search to return as soon as possible. The result of the interrupted
+    /// search is meaningless and should be discarded.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Searches stop by themselves once `deadline` has passed or `node_limit` nodes were visited
+    /// since the statistics were last reset.
+    pub fn set_limits(&mut self, deadline: Option<Instant>, node_limit: Option<u64>) {
+        self.deadline = deadline;
+        self.node_limit = node_limit;
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = SearchStats::default();
+    }
+
+    pub fn clear_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Finds the best `n` of the allowed root moves by repeatedly solving with the previously found
+    /// moves excluded. Returns the score, move and principal variation of every line completed before
+    /// the search was stopped, best first.
+    pub fn solve_multipv(&mut self, board: &Board, depth: i32, pvs: bool, n: usize) -> Vec<(i32, TMove, Vec<TMove>)> {
+        let allowed = self.root_moves.take();
+        let mut lines = Vec::new();
+        let mut remaining = board.get_possible_moves();
+        remaining.retain(|tmove| allowed.as_ref().is_none_or(|allowed| allowed.contains(tmove)));
+        while lines.len() < n && !remaining.is_empty() {
+            self.root_moves = Some(remaining.clone());
+            match self.solve(board, depth, pvs) {
+                _ if self.is_stopped() => { break; }
+                (score, Some(tmove)) => {
+                    let mut pv = vec![tmove];
+                    pv.extend(self.principal_variation(&board.make_move(&tmove), depth as usize));
+                    remaining.retain(|m| *m != tmove);
+                    lines.push((score, tmove, pv));
+                }
+                (_, None) => { break; }
+            }
         }
+        self.root_moves = allowed;
+        lines
+    }
+
+    /// Follows the moves stored in the transposition table from `board`, stopping at positions
+    /// without an entry, decided games and repetitions.
+    pub fn principal_variation(&self, board: &Board, max_len: usize) -> Vec<TMove> {
+        let mut pv = Vec::new();
+        let mut seen = vec![board.board];
+        let mut current = *board;
+        while pv.len() < max_len && current.get_winner().is_none() {
+            match self.map.get(current.board) {
+                Some(tentry) => {
+                    current = current.make_move(&tentry.tmove);
+                    if seen.contains(&current.board) { break; }
+                    seen.push(current.board);
+                    pv.push(tentry.tmove);
+                }
+                None => { break; }
+            }
+        }
+        pv
     }
 
     pub fn new() -> Negamax {
-        Negamax { map: MetroHashMap::default(), pvs_failed_calls: 0, zero_window_calls: 0, normal_calls: 0, transpo_calls: 0 }
+        Negamax::with_table(Arc::new(TranspositionTable::new()))
+    }
+
+    /// Creates a search that shares its transposition table with other searches.
+    pub fn with_table(map: Arc<TranspositionTable>) -> Negamax {
+        Negamax { map, killers: [[None; 2]; MAX_PLY], history: [[0; 25]; 25], stats: SearchStats::default(), null_move: false, lmr: false, move_ordering: true, root_moves: None, threads: 1, tablebase: None, helper_id: 0, root_best: None, deadline: None, node_limit: None, stop: Arc::new(AtomicBool::new(false)), in_null_move: false }
     }
 }
 
+impl Default for Negamax {
+    fn default() -> Self {
+        Negamax::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{START, BENCHMARK, EIGHTEEN_MOVE};
+
+    #[test]
+    fn test_search_beyond_killer_table() {
+        let board = START;
+        let mut negamax = Negamax::new();
+        let d = MAX_PLY as i32 - 2;
+        let (_, tmove) = negamax.negamax(&board, d, d + 4, -INFINITY, INFINITY);
+        assert!(board.get_possible_moves().contains(&tmove.unwrap()));
+    }
+
+    #[test]
+    fn test_move_ordering_saves_nodes() {
+        for board in [START, BENCHMARK, EIGHTEEN_MOVE] {
+            let nodes = |move_ordering: bool| {
+                let mut negamax = Negamax::new();
+                negamax.move_ordering = move_ordering;
+                let (score, _) = negamax.solve(&board, 6, true);
+                (score, negamax.stats.nodes)
+            };
+            let (ordered_score, ordered) = nodes(true);
+            let (unordered_score, unordered) = nodes(false);
+            println!("{:#x}: {ordered} nodes with killers and history, {unordered} without", board.board);
+            assert_eq!(ordered_score, unordered_score);
+            assert!(ordered < unordered);
+        }
+    }
+
+    #[test]
+    fn test_quiescence_sees_escapes() {
+        let square = |x: u32, y: u32| 1 << (24 - (5 * y + x));
+        // the king on c3 has all four lines to the edge open, two attackers sit in the corners
+        let (attackers, king) = (square(0, 0) | square(4, 4), square(2, 2));
+        let defending = Board::from_bitboards(0, attackers, 0, king);
+        let mut negamax = Negamax::new();
+        assert_eq!(negamax.quiescence(&defending, 0, QUIESCENCE_DEPTH, -INFINITY, INFINITY), win_in(2));
+
+        // the attackers cannot stand pat against the threat and no single move closes every line
+        let attacking = Board::from_bitboards(1, attackers, 0, king);
+        assert_eq!(negamax.quiescence(&attacking, 0, QUIESCENCE_DEPTH, -INFINITY, INFINITY), loss_in(3));
+        assert!(negamax.stats.quiescence_calls > 0);
+    }
+
+    #[test]
+    fn test_pruning_keeps_the_win() {
+        // the defenders win in 7 with b2-d2, which takes depth 8 to see
+        let board = BENCHMARK;
+        for pvs in [false, true] {
+            let mut plain = Negamax::new();
+            let expected = plain.solve(&board, 8, pvs);
+            assert!(is_decided(expected.0));
+
+            let mut pruned = Negamax::new();
+            pruned.null_move = true;
+            pruned.lmr = true;
+            assert_eq!(pruned.solve(&board, 8, pvs), expected);
+            assert!(pruned.stats.null_move_calls > 0 && pruned.stats.reduced_calls > 0);
+            assert!(pruned.stats.nodes < plain.stats.nodes);
+        }
+    }
+
+    #[test]
+    fn test_aspiration_agrees_with_full_window() {
+        for board in [START, BENCHMARK] {
+            let mut full = Negamax::new();
+            let mut aspiration = Negamax::new();
+            let mut guess = 0;
+            for depth in 1..=6 {
+                let (score, _) = full.solve(&board, depth, true);
+                assert_eq!(aspiration.solve_aspiration(&board, depth, true, guess).0, score);
+                // a guess far off has to be widened until it holds the score
+                let mut widened = Negamax::new();
+                assert_eq!(widened.solve_aspiration(&board, depth, false, score + 100).0, score);
+                assert!(is_decided(score) || widened.stats.aspiration_researches > 0);
+                guess = score;
+            }
+        }
+    }
+
+    #[test]
+    fn test_principal_variation_is_legal() {
+        for board in [START, BENCHMARK] {
+            let mut negamax = Negamax::new();
+            let (_, tmove) = negamax.solve(&board, 6, true);
+            let pv = negamax.principal_variation(&board, 7);
+            assert_eq!(pv.first().copied(), tmove);
+            let mut seen = vec![board.board];
+            let mut current = board;
+            for tmove in &pv {
+                assert!(current.get_winner().is_none());
+                assert!(current.moves().contains(tmove));
+                current = current.make_move(tmove);
+                assert!(!seen.contains(&current.board));
+                seen.push(current.board);
+            }
+        }
+    }
+
+    #[test]
+    fn test_multipv_lines() {
+        let board = START;
+        let (score, _) = Negamax::new().solve(&board, 4, true);
+        let mut negamax = Negamax::new();
+        let lines = negamax.solve_multipv(&board, 4, true, 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].0, score);
+        assert!(lines.windows(2).all(|pair| pair[0].0 >= pair[1].0 && pair[0].1 != pair[1].1));
+        assert!(lines.iter().all(|(_, tmove, pv)| pv[0] == *tmove));
+        assert!(negamax.root_moves.is_none());
+    }
+
+    #[test]
+    fn test_lazy_smp_agrees_with_single_thread() {
+        for board in [START, BENCHMARK] {
+            let mut single = Negamax::new();
+            let mut parallel = Negamax::new();
+            parallel.threads = 3;
+            for depth in 1..=6 {
+                let (score, _) = single.solve(&board, depth, true);
+                let (parallel_score, tmove) = parallel.solve(&board, depth, true);
+                assert_eq!(parallel_score, score);
+                assert!(board.moves().contains(&tmove.unwrap()));
+                // the helpers search deeper and may store other root moves
+                assert_eq!(parallel.principal_variation(&board, depth as usize + 1).first().copied(), tmove);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stopped_search_keeps_tables() {
+        let board = START;
+        let mut negamax = Negamax::new();
+        let full = {
+            let mut counting = Negamax::new();
+            counting.solve(&board, 7, true);
+            counting.stats.nodes
+        };
+        let (_, tmove) = negamax.solve(&board, 4, true);
+        // stops after the first root moves, the scores of the aborted ones are made up
+        negamax.set_limits(None, Some(full * 2 / 3));
+        negamax.solve(&board, 7, true);
+        assert!(negamax.is_stopped());
+        assert_eq!(negamax.map.get(board.board).map(|tentry| tentry.tmove), tmove);
+    }
+}
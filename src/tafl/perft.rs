@@ -0,0 +1,58 @@
+use super::board::Board;
+use super::tmove::TMove;
+
+/// Counts the positions reachable in exactly `depth` moves. Decided games are not continued, so
+/// they only count if they are reached at the last move.
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if board.get_winner().is_some() {
+        return 0;
+    }
+
+    let possible_moves = board.moves();
+    if depth == 1 {
+        return possible_moves.len() as u64;
+    }
+    possible_moves.iter().map(|tmove| perft(&board.make_move(tmove), depth - 1)).sum()
+}
+
+/// Perft split by root move, to narrow down move generation differences to a single line.
+pub fn divide(board: &Board, depth: u32) -> Vec<(TMove, u64)> {
+    if depth == 0 || board.get_winner().is_some() {
+        return Vec::new();
+    }
+    board.moves().iter().map(|tmove| (*tmove, perft(&board.make_move(tmove), depth - 1))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{START, BENCHMARK, EIGHTEEN_MOVE};
+
+    // perft counts of the preset boards of the command line, checked against an independent
+    // implementation of the rules
+    const KNOWN_COUNTS: [(&str, Board, [u64; 5]); 3] = [
+        ("start", START, [16, 256, 4048, 65984, 1037832]),
+        ("benchmark", BENCHMARK, [13, 242, 3132, 54325, 705242]),
+        ("18move", EIGHTEEN_MOVE, [11, 225, 2637, 50068, 620079]),
+    ];
+
+    #[test]
+    fn test_perft_known_counts() {
+        for (name, board, counts) in KNOWN_COUNTS {
+            for (depth, count) in counts.iter().enumerate() {
+                assert_eq!(perft(&board, depth as u32 + 1), *count, "{name} at depth {}", depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_divide_sums_to_perft() {
+        let board = KNOWN_COUNTS[0].1;
+        let divided = divide(&board, 3);
+        assert_eq!(divided.len(), 16);
+        assert_eq!(divided.iter().map(|(_, count)| count).sum::<u64>(), perft(&board, 3));
+    }
+}
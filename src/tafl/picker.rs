@@ -0,0 +1,155 @@
+use std::vec;
+use super::board::Board;
+use super::tmove::{MoveList, TMove, MAX_MOVES};
+
+#[inline]
+fn square(pos: (u8, u8)) -> usize {
+    pos.1 as usize * 5 + pos.0 as usize
+}
+
+/// Stages of move picking, in the order their moves are handed out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Captures,
+    Killers,
+    Quiets,
+    Done,
+}
+
+/// Hands out the moves of a node best first: captures, the killer moves of the ply, then quiet moves
+/// by history score. A stage is only scored once it is reached, so a cutoff early in the list skips
+/// most of the ordering work. The few captures and killers are selected one at a time, the quiet moves
+/// are sorted once their stage is reached.
+pub struct MovePicker {
+    /// Moves that were not part of a stage yet, in the order of the move generator.
+    rest: MoveList,
+    staged: [(i32, TMove); MAX_MOVES],
+    staged_len: usize,
+    /// Moves of the current stage before `next` were handed out.
+    next: usize,
+    killers: [Option<TMove>; 2],
+    skip: Option<TMove>,
+    /// `None` until the first stage was entered.
+    stage: Option<Stage>,
+    presorted: Option<vec::IntoIter<(TMove, Stage)>>,
+}
+
+impl MovePicker {
+    /// Picks from `moves` except `skip`, usually the transposition table move that was searched already.
+    pub fn new(moves: MoveList, killers: [Option<TMove>; 2], skip: Option<TMove>) -> MovePicker {
+        let staged = [(0, TMove { start: (0, 0), end: (0, 0) }); MAX_MOVES];
+        MovePicker { rest: moves, staged, staged_len: 0, next: 0, killers, skip, stage: None, presorted: None }
+    }
+
+    /// Takes the moves matching `belongs` out of the rest, scores them and makes them the current stage.
+    fn enter_stage<F, S>(&mut self, stage: Stage, mut belongs: F, mut score: S)
+    where F: FnMut(&TMove) -> bool, S: FnMut(&TMove) -> i32 {
+        let mut staged_len = 0;
+        let staged = &mut self.staged;
+        self.rest.retain(|tmove| {
+            if !belongs(tmove) { return true; }
+            staged[staged_len] = (score(tmove), *tmove);
+            staged_len += 1;
+            false
+        });
+        self.stage = Some(stage);
+        self.staged_len = staged_len;
+        self.next = 0;
+    }
+
+    fn advance(&mut self, board: &Board, history: &[[i32; 25]; 25]) {
+        let quiet_score = |tmove: &TMove| history[square(tmove.start)][square(tmove.end)];
+        match self.stage {
+            None => self.enter_stage(Stage::Captures, |tmove| board.is_capture(tmove), quiet_score),
+            Some(Stage::Captures) => {
+                let killers = self.killers;
+                self.enter_stage(Stage::Killers, |tmove| killers.contains(&Some(*tmove)),
+                    |tmove| (killers[0] == Some(*tmove)) as i32);
+            }
+            Some(Stage::Killers) => {
+                self.enter_stage(Stage::Quiets, |_| true, quiet_score);
+                // stable, so equally scored moves stay in the order of the move generator
+                self.staged[..self.staged_len].sort_by_key(|(score, _)| -score);
+            }
+            Some(_) => self.stage = Some(Stage::Done)
+        }
+    }
+
+    /// The next best move and the stage it was picked in, or `None` once all moves were handed out.
+    pub fn next(&mut self, board: &Board, history: &[[i32; 25]; 25]) -> Option<(TMove, Stage)> {
+        if let Some(presorted) = &mut self.presorted {
+            return presorted.next();
+        }
+        loop {
+            let stage = match self.stage {
+                Some(Stage::Done) => { return None; }
+                Some(stage) if self.next < self.staged_len => stage,
+                _ => {
+                    self.advance(board, history);
+                    continue;
+                }
+            };
+
+            if stage != Stage::Quiets {
+                // the first of equally scored moves is picked, the passed over ones keep their order
+                let mut best = self.next;
+                for i in self.next + 1..self.staged_len {
+                    if self.staged[i].0 > self.staged[best].0 { best = i; }
+                }
+                self.staged[self.next..=best].rotate_right(1);
+            }
+            let tmove = self.staged[self.next].1;
+            self.next += 1;
+            if Some(tmove) != self.skip {
+                return Some((tmove, stage));
+            }
+        }
+    }
+
+    /// Orders all moves and rotates them by `n`, so that search threads start with different moves.
+    pub fn rotate(&mut self, board: &Board, history: &[[i32; 25]; 25], n: usize) {
+        let mut ordered = Vec::new();
+        while let Some(picked) = self.next(board, history) {
+            ordered.push(picked);
+        }
+        if !ordered.is_empty() {
+            let len = ordered.len();
+            ordered.rotate_left(n % len);
+        }
+        self.presorted = Some(ordered.into_iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{START};
+
+    #[test]
+    fn test_stages() {
+        // follow the first quiet moves from the start until a capture is possible
+        let mut board = START;
+        while !board.moves().iter().any(|tmove| board.is_capture(tmove)) {
+            board = board.make_move(&board.moves()[0]);
+        }
+        let moves = board.moves();
+        let capture = *moves.iter().find(|tmove| board.is_capture(tmove)).unwrap();
+        let quiets = moves.iter().filter(|tmove| !board.is_capture(tmove)).copied().collect::<Vec<TMove>>();
+        let (killer, skip) = (quiets[quiets.len() - 1], quiets[0]);
+        let mut history = [[0; 25]; 25];
+        history[square(quiets[1].start)][square(quiets[1].end)] = 10;
+
+        let mut picker = MovePicker::new(moves, [None, Some(killer)], Some(skip));
+        let mut picked = Vec::new();
+        while let Some(next) = picker.next(&board, &history) {
+            picked.push(next);
+        }
+
+        assert_eq!(picked.len(), moves.len() - 1);
+        assert!(!picked.iter().any(|(tmove, _)| *tmove == skip));
+        assert_eq!(picked[0], (capture, Stage::Captures));
+        let killer_at = picked.iter().position(|picked| *picked == (killer, Stage::Killers)).unwrap();
+        assert_eq!(picked[killer_at + 1], (quiets[1], Stage::Quiets));
+        assert!(picked[killer_at + 1..].iter().all(|(_, stage)| *stage == Stage::Quiets));
+    }
+}
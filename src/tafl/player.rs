@@ -0,0 +1,323 @@
+use std::str::FromStr;
+use std::time::Instant;
+use text_io::read;
+use super::board::Board;
+use super::game::Game;
+use super::mcts::Mcts;
+use super::negamax::Negamax;
+use super::search::{iterative_deepening, DepthResult, SearchLimits};
+use super::tmove::TMove;
+
+// games without a result after this many plies are drawn
+const MAX_GAME_PLIES: usize = 400;
+
+/// Called after every iteration of an engine with the position it searches.
+pub type Report<E> = Box<dyn FnMut(&Board, &E, &DepthResult)>;
+
+/// A move chosen by a player and what the player found out about the position.
+pub struct Choice {
+    pub tmove: TMove,
+    /// The last iteration of an engine, `None` for players that do not search.
+    pub result: Option<DepthResult>,
+}
+
+/// Anything that picks moves: people at the console and engines alike.
+pub trait Player {
+    fn name(&self) -> String;
+
+    /// Picks a move in the current position of `game` within `limits`, which players that do not
+    /// search may ignore. `None` if the player gives up or has no move.
+    fn choose(&mut self, game: &Game, limits: &SearchLimits) -> Option<Choice>;
+}
+
+/// Searches the position of `game` with `search`, leaving out the moves that would draw by
+/// repetition: engines see no history, the game judges by it. The draw is taken if the best of the
+/// other moves scores worse, and searched like any other move if every move draws.
+fn choose_avoiding_draws<S>(game: &Game, limits: &SearchLimits, mut search: S) -> Option<Choice>
+where S: FnMut(&SearchLimits) -> Option<DepthResult> {
+    let board = game.board();
+    let (drawing, others): (Vec<TMove>, Vec<TMove>) = board.moves().iter()
+        .filter(|tmove| limits.root_moves.as_ref().is_none_or(|allowed| allowed.contains(tmove)))
+        .partition(|tmove| game.repeats(tmove));
+    if others.is_empty() {
+        let result = search(limits)?;
+        return Some(Choice { tmove: result.tmove?, result: Some(result) });
+    }
+    let result = search(&SearchLimits { root_moves: Some(others), ..limits.clone() })?;
+    let tmove = match drawing.first() {
+        Some(draw) if result.score < 0 => *draw,
+        _ => result.tmove?
+    };
+    Some(Choice { tmove, result: Some(result) })
+}
+
+/// A move for an engine whose search was stopped before it finished any root move: `preferred` if
+/// it is possible and allowed, else the first allowed move that does not repeat a position, else any
+/// allowed move. Root moves that are all impossible allow every move. `None` only without moves.
+fn fallback_move(game: &Game, limits: &SearchLimits, preferred: Option<TMove>) -> Option<Choice> {
+    let moves = game.board().moves();
+    let mut allowed = moves.iter().copied()
+        .filter(|tmove| limits.root_moves.as_ref().is_none_or(|root_moves| root_moves.contains(tmove)))
+        .collect::<Vec<TMove>>();
+    if allowed.is_empty() {
+        allowed = moves.to_vec();
+    }
+    let tmove = preferred.filter(|tmove| allowed.contains(tmove))
+        .or_else(|| allowed.iter().find(|tmove| !game.repeats(tmove)).copied())
+        .or_else(|| allowed.first().copied())?;
+    Some(Choice { tmove, result: None })
+}
+
+/// Reads moves like `b3-b5` from the console until a possible one is entered, `exit` gives up.
+pub struct HumanPlayer;
+
+impl Player for HumanPlayer {
+    fn name(&self) -> String {
+        "human".to_string()
+    }
+
+    fn choose(&mut self, game: &Game, _limits: &SearchLimits) -> Option<Choice> {
+        let possible_moves = game.board().get_possible_moves();
+        let mut input = "".to_string();
+        while input.to_lowercase().as_str() != "exit" {
+            println!("Enter move: ");
+            input = read!();
+            match TMove::from_str(&input) {
+                Ok(tmove) => {
+                    if possible_moves.contains(&tmove) {
+                        return Some(Choice { tmove, result: None });
+                    }
+                    println!("Not a valid move!");
+                }
+                Err(_) => { println!("Syntax Error!") }
+            }
+        }
+        None
+    }
+}
+
+/// Alpha-beta search by iterative deepening, as plain negamax or as principal variation search.
+pub struct NegamaxPlayer {
+    pub negamax: Negamax,
+    pub pvs: bool,
+    pub report: Option<Report<Negamax>>,
+}
+
+impl NegamaxPlayer {
+    pub fn new(negamax: Negamax, pvs: bool) -> NegamaxPlayer {
+        NegamaxPlayer { negamax, pvs, report: None }
+    }
+}
+
+impl Player for NegamaxPlayer {
+    fn name(&self) -> String {
+        match self.pvs {
+            true => "pvs".to_string(),
+            false => "negamax".to_string()
+        }
+    }
+
+    fn choose(&mut self, game: &Game, limits: &SearchLimits) -> Option<Choice> {
+        let board = game.board();
+        let (negamax, report, pvs) = (&mut self.negamax, &mut self.report, self.pvs);
+        choose_avoiding_draws(game, limits, |limits| {
+            negamax.clear_stop();
+            iterative_deepening(negamax, &board, limits, pvs, |negamax, result| {
+                if let Some(report) = report {
+                    report(&board, negamax, result);
+                }
+            })
+        }).or_else(|| fallback_move(game, limits, self.negamax.map.get(board.board).map(|tentry| tentry.tmove)))
+    }
+}
+
+/// Monte Carlo tree search, keeping its tree from move to move.
+pub struct MctsPlayer {
+    pub mcts: Mcts,
+    pub report: Option<Report<Mcts>>,
+}
+
+impl MctsPlayer {
+    pub fn new(mcts: Mcts) -> MctsPlayer {
+        MctsPlayer { mcts, report: None }
+    }
+}
+
+impl Player for MctsPlayer {
+    fn name(&self) -> String {
+        "mcts".to_string()
+    }
+
+    fn choose(&mut self, game: &Game, limits: &SearchLimits) -> Option<Choice> {
+        let board = game.board();
+        let (mcts, report) = (&mut self.mcts, &mut self.report);
+        choose_avoiding_draws(game, limits, |limits| {
+            mcts.clear_stop();
+            mcts.search(&board, limits, |mcts, result| {
+                if let Some(report) = report {
+                    report(&board, mcts, result);
+                }
+            })
+        }).or_else(|| fallback_move(game, limits, None))
+    }
+}
+
+/// Plays `game` on to its end, `players[0]` with the defenders and `players[1]` with the attackers.
+/// Moves are timed, and while the game has clocks the players search within the budget of their
+/// clock instead of the time in `limits`. The root moves of `limits` only restrict the first move
+/// of the game. `on_move` is called with the game after every move.
+pub fn play_game<F>(players: [&mut dyn Player; 2], game: &mut Game, limits: &SearchLimits, mut on_move: F)
+where F: FnMut(&Game, &Option<DepthResult>) {
+    let [defenders, attackers] = players;
+    while !game.is_over() {
+        if game.len() >= MAX_GAME_PLIES {
+            game.declare_draw();
+            break;
+        }
+        let board = game.board();
+        let player: &mut dyn Player = match board.get_player() {
+            0 => &mut *defenders,
+            _ => &mut *attackers
+        };
+        let root_moves = limits.root_moves.clone().filter(|_| game.is_empty());
+        let limits = SearchLimits { time: game.budget().or(limits.time), root_moves, ..limits.clone() };
+        let t0 = Instant::now();
+        let played = player.choose(game, &limits).filter(|choice| game.play_timed(&choice.tmove, Some(t0.elapsed())).is_ok());
+        match played {
+            Some(choice) => { on_move(game, &choice.result); }
+            None => { game.resign(); }
+        }
+    }
+}
+
+/// Points of two players over a match, a draw counting half for each.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchScore {
+    pub first: f32,
+    pub second: f32,
+    pub games: u32,
+}
+
+/// Plays `games` games between two players from `start`, the first player taking the defenders in
+/// the even games and the attackers in the odd ones. `on_move` is called after every move like in
+/// `play_game`, `on_game` after every game with its number, the index of the player that had the
+/// defenders and the finished game.
+pub fn play_match<M, F>(first: &mut dyn Player, second: &mut dyn Player, start: &Board, limits: &SearchLimits, games: u32, mut on_move: M, mut on_game: F) -> MatchScore
+where M: FnMut(&Game, &Option<DepthResult>), F: FnMut(u32, usize, &Game) {
+    let mut score = MatchScore::default();
+    for index in 0..games {
+        let defending = (index % 2) as usize;
+        let players: [&mut dyn Player; 2] = match defending {
+            0 => [&mut *first, &mut *second],
+            _ => [&mut *second, &mut *first]
+        };
+        let mut game = Game::new(*start);
+        play_game(players, &mut game, limits, &mut on_move);
+        let result = game.result().unwrap();
+        let points = |side: u8| match result.winner() {
+            Some(winner) if winner == side => 1.0,
+            Some(_) => 0.0,
+            None => 0.5
+        };
+        // the first player has the defenders, side 0, in even games
+        let first_side = defending as u8;
+        score.first += points(first_side);
+        score.second += points(1 - first_side);
+        score.games += 1;
+        on_game(index, defending, &game);
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use super::super::clock::TimeBudget;
+    use super::super::game::GameResult;
+    use super::super::testing::{START};
+
+    /// Plays the first possible move.
+    struct FirstMove;
+
+    impl Player for FirstMove {
+        fn name(&self) -> String {
+            "first".to_string()
+        }
+
+        fn choose(&mut self, game: &Game, _limits: &SearchLimits) -> Option<Choice> {
+            game.board().get_possible_moves().first().map(|tmove| Choice { tmove: *tmove, result: None })
+        }
+    }
+
+    #[test]
+    fn test_engines_beat_first_move_player() {
+        let board = START;
+        let limits = SearchLimits { max_depth: 4, nodes: Some(20_000), ..SearchLimits::default() };
+        let mut negamax = NegamaxPlayer::new(Negamax::new(), true);
+        let score = play_match(&mut negamax, &mut FirstMove, &board, &limits, 2, |_, _| {}, |_, _, _| {});
+        assert_eq!(score.games, 2);
+        assert!(score.first > score.second);
+
+        let mut mcts = MctsPlayer::new(Mcts::new());
+        let mut game = Game::new(board);
+        let mut moves = 0;
+        play_game([&mut mcts, &mut FirstMove], &mut game, &limits, |_, _| moves += 1);
+        assert_eq!(game.result(), Some(GameResult::Win(0)));
+        assert_eq!(game.len(), moves);
+    }
+
+    #[test]
+    fn test_root_moves_only_restrict_the_first_move() {
+        let board = START;
+        let opening = TMove::from_str("b3-b4").unwrap();
+        let limits = SearchLimits { max_depth: 2, root_moves: Some(vec![opening]), ..SearchLimits::default() };
+        let mut games = Vec::new();
+        let mut negamax = NegamaxPlayer::new(Negamax::new(), true);
+        play_match(&mut negamax, &mut FirstMove, &board, &limits, 2, |_, _| {}, |_, defending, game| {
+            games.push((defending, game.plies().iter().map(|ply| ply.tmove).collect::<Vec<TMove>>()));
+        });
+        for (defending, moves) in games {
+            assert!(moves.len() > 2);
+            if defending == 0 {
+                assert_eq!(moves[0], opening);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stopped_search_still_moves() {
+        let board = START;
+        // no root move of the first iteration is finished in time
+        let limits = SearchLimits { start_depth: 8, time: Some(TimeBudget::fixed(Duration::ZERO)), ..SearchLimits::default() };
+        let mut negamax = NegamaxPlayer::new(Negamax::new(), true);
+        let choice = negamax.choose(&Game::new(board), &limits).unwrap();
+        assert!(choice.result.is_none());
+        assert!(board.moves().contains(&choice.tmove));
+
+        // a side without moves has nothing to play
+        let blocked = Board { board: 0b0_0000000000_0000000000_0000000000_0000000000_0000000000 };
+        assert!(blocked.moves().is_empty());
+        assert!(fallback_move(&Game::new(blocked), &limits, None).is_none());
+    }
+
+    #[test]
+    fn test_repetitions_are_avoided_unless_losing() {
+        let board = START;
+        let mut game = Game::new(board);
+        for tmove in ["b3-b4", "a1-b1", "b4-b3"] {
+            game.play(&TMove::from_str(tmove).unwrap()).unwrap();
+        }
+        let repeating = TMove::from_str("b1-a1").unwrap();
+        // stands in for an engine that picks the first allowed move with the given score
+        let search = |score: i32| move |limits: &SearchLimits| {
+            let root_moves = limits.root_moves.clone().unwrap();
+            assert!(!root_moves.contains(&repeating));
+            Some(DepthResult { depth: 1, score, tmove: Some(root_moves[0]), pv: vec![root_moves[0]], elapsed: 0.0,
+                complete: true, stats: Default::default(), branching_factor: None, lines: Vec::new() })
+        };
+        let limits = SearchLimits::default();
+        assert_ne!(choose_avoiding_draws(&game, &limits, search(5)).unwrap().tmove, repeating);
+        assert_eq!(choose_avoiding_draws(&game, &limits, search(-5)).unwrap().tmove, repeating);
+    }
+}
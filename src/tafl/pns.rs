@@ -0,0 +1,311 @@
+use std::mem::size_of;
+use std::sync::Arc;
+use super::board::Board;
+use super::proof::ProofTree;
+use super::solver::Value;
+use super::tablebase::Tablebase;
+use super::tmove::TMove;
+
+/// Proof or disproof number of a solved node.
+pub const INFINITE: u32 = u32::MAX;
+
+/// Result of a proof-number search for the side to move at the root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The side to move wins.
+    Proven,
+    /// The side to move does not win: it loses or the opponent holds a draw by repetition.
+    Disproven,
+    /// The memory limit was reached first.
+    Unknown,
+}
+
+const NO_NODE: u32 = u32::MAX;
+
+/// Where the proof of a position comes from when exporting it.
+enum Source {
+    Tree(u32),
+    /// A position below a leaf that the tablebase solved.
+    Tablebase(Board),
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    board: Board,
+    /// The move leading here from the parent.
+    tmove: TMove,
+    parent: u32,
+    /// Children are stored consecutively, `first_child` is `NO_NODE` until the node is expanded.
+    first_child: u32,
+    children: u16,
+    proof: u32,
+    disproof: u32,
+}
+
+/// Proof-number search: grows a tree of the game best first, always expanding the leaf that is most
+/// proving, the one that helps most to prove the win and to disprove it at the same time. The proof
+/// number of a node is the least number of leaves that have to be proven to prove the win, the
+/// disproof number the least number to disprove it. The prover chooses one move, so its nodes take
+/// the smallest proof number of their children and the sum of their disproof numbers, the opponent's
+/// nodes the other way around.
+///
+/// Positions are not shared between branches, so that a position repeating one of its ancestors can
+/// count as disproven: the prover cannot win by going around in circles.
+pub struct ProofSearch {
+    nodes: Vec<Node>,
+    /// Nodes the tree may grow to.
+    pub max_nodes: usize,
+    /// Exact values of positions with little material, used for leaves.
+    pub tablebase: Option<Arc<Tablebase>>,
+    prover: u8,
+}
+
+fn saturating_sum<I: Iterator<Item = u32>>(numbers: I) -> u32 {
+    numbers.fold(0, |sum: u32, number| sum.saturating_add(number))
+}
+
+impl ProofSearch {
+    pub fn new(max_nodes: usize) -> ProofSearch {
+        ProofSearch { nodes: Vec::new(), max_nodes, tablebase: None, prover: 0 }
+    }
+
+    /// A search whose tree takes at most about `bytes` of memory.
+    pub fn with_memory(bytes: usize) -> ProofSearch {
+        ProofSearch::new(bytes / size_of::<Node>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Proof and disproof number of the root.
+    pub fn root_numbers(&self) -> Option<(u32, u32)> {
+        self.nodes.first().map(|root| (root.proof, root.disproof))
+    }
+
+    fn repeats(&self, board: &Board, mut parent: u32) -> bool {
+        while parent != NO_NODE {
+            let node = &self.nodes[parent as usize];
+            if node.board.board == board.board { return true; }
+            parent = node.parent;
+        }
+        false
+    }
+
+    /// Initial numbers of a new leaf. Decided positions are solved right away, otherwise the numbers
+    /// start at the mobility of the side to move: the more moves it has, the harder it is to refute.
+    fn evaluate(&self, board: &Board, parent: u32) -> (u32, u32) {
+        let proven = (0, INFINITE);
+        let disproven = (INFINITE, 0);
+        let mover_wins = |wins: bool| match wins == (board.get_player() == self.prover) {
+            true => proven,
+            false => disproven
+        };
+        if let Some(winner) = board.get_winner() {
+            return match winner == self.prover { true => proven, false => disproven };
+        }
+        if let Some(value) = self.tablebase.as_ref().and_then(|tablebase| tablebase.probe(board)) {
+            return match value {
+                Value::Win(_) => mover_wins(true),
+                Value::Loss(_) => mover_wins(false),
+                Value::Draw => disproven
+            };
+        }
+        if self.repeats(board, parent) { return disproven; }
+        match board.moves().len() as u32 {
+            0 => mover_wins(false),
+            moves if board.get_player() == self.prover => (1, moves),
+            moves => (moves, 1)
+        }
+    }
+
+    fn push(&mut self, board: Board, tmove: TMove, parent: u32) {
+        let (proof, disproof) = self.evaluate(&board, parent);
+        self.nodes.push(Node { board, tmove, parent, first_child: NO_NODE, children: 0, proof, disproof });
+    }
+
+    fn children(&self, index: u32) -> std::ops::Range<usize> {
+        let node = &self.nodes[index as usize];
+        node.first_child as usize..node.first_child as usize + node.children as usize
+    }
+
+    fn is_prover_node(&self, index: u32) -> bool {
+        self.nodes[index as usize].board.get_player() == self.prover
+    }
+
+    /// Follows the children with the smallest proof numbers at the prover's nodes and the smallest
+    /// disproof numbers at the opponent's nodes down to a leaf.
+    fn most_proving(&self) -> u32 {
+        let mut index = 0;
+        while self.nodes[index as usize].first_child != NO_NODE {
+            let children = self.children(index);
+            index = match self.is_prover_node(index) {
+                true => children.min_by_key(|child| self.nodes[*child].proof),
+                false => children.min_by_key(|child| self.nodes[*child].disproof)
+            }.unwrap() as u32;
+        }
+        index
+    }
+
+    fn expand(&mut self, index: u32) {
+        let board = self.nodes[index as usize].board;
+        let moves = board.moves();
+        let first_child = self.nodes.len() as u32;
+        for tmove in moves.iter() {
+            self.push(board.make_move(tmove), *tmove, index);
+        }
+        let node = &mut self.nodes[index as usize];
+        node.first_child = first_child;
+        node.children = moves.len() as u16;
+    }
+
+    /// Recomputes the numbers from `index` up to the root, stopping where they do not change.
+    fn update(&mut self, mut index: u32) {
+        while index != NO_NODE {
+            let children = self.children(index);
+            let proofs = self.nodes[children.clone()].iter().map(|child| child.proof);
+            let disproofs = self.nodes[children.clone()].iter().map(|child| child.disproof);
+            let (proof, disproof) = match self.is_prover_node(index) {
+                true => (proofs.min().unwrap(), saturating_sum(disproofs)),
+                false => (saturating_sum(proofs), disproofs.min().unwrap())
+            };
+            let node = &mut self.nodes[index as usize];
+            if (node.proof, node.disproof) == (proof, disproof) { return; }
+            node.proof = proof;
+            node.disproof = disproof;
+            index = node.parent;
+        }
+    }
+
+    /// Proves or disproves that the side to move in `board` wins. The tree is kept until the next
+    /// search, for `best_move` and the proof.
+    pub fn search(&mut self, board: &Board) -> Outcome {
+        self.nodes.clear();
+        self.prover = board.get_player();
+        self.push(*board, TMove { start: (0, 0), end: (0, 0) }, NO_NODE);
+        loop {
+            match self.root_numbers() {
+                Some((0, _)) => { return Outcome::Proven; }
+                Some((_, 0)) => { return Outcome::Disproven; }
+                _ => {}
+            }
+            let leaf = self.most_proving();
+            if self.nodes.len() + self.nodes[leaf as usize].board.moves().len() > self.max_nodes {
+                return Outcome::Unknown;
+            }
+            self.expand(leaf);
+            self.update(leaf);
+        }
+    }
+
+    /// A winning move once the win is proven, otherwise the most promising move.
+    pub fn best_move(&self) -> Option<TMove> {
+        if self.nodes.is_empty() { return None; }
+        self.children(0).min_by_key(|child| self.nodes[*child].proof).map(|child| self.nodes[child].tmove)
+    }
+
+    fn source_board(&self, source: &Source) -> Board {
+        match source {
+            Source::Tree(index) => self.nodes[*index as usize].board,
+            Source::Tablebase(board) => *board
+        }
+    }
+
+    /// Moves of the strategy in a proven position: one winning move of the prover or all replies of
+    /// the opponent. Below the leaves the tablebase solved, the prover takes its fastest win.
+    fn proof_moves(&self, source: &Source) -> Option<Vec<(TMove, Source)>> {
+        let board = self.source_board(source);
+        let proving = board.get_player() == self.prover;
+        if let Source::Tree(index) = source {
+            if self.nodes[*index as usize].first_child != NO_NODE {
+                let mut children = self.children(*index).filter(|child| !proving || self.nodes[*child].proof == 0);
+                let moves = match proving {
+                    true => children.next().into_iter().collect::<Vec<usize>>(),
+                    false => children.collect()
+                };
+                return Some(moves.into_iter().map(|child| (self.nodes[child].tmove, Source::Tree(child as u32))).collect());
+            }
+        }
+
+        let moves = board.moves();
+        let children = moves.iter().map(|tmove| (*tmove, board.make_move(tmove)));
+        if !proving {
+            return Some(children.map(|(tmove, child)| (tmove, Source::Tablebase(child))).collect());
+        }
+        let tablebase = self.tablebase.as_ref()?;
+        let fastest = children.filter_map(|(tmove, child)| {
+            let plies = match (child.get_winner(), tablebase.probe(&child)) {
+                (Some(winner), _) if winner == self.prover => 0,
+                (None, Some(Value::Loss(Some(plies)))) => plies + 1,
+                _ => { return None; }
+            };
+            Some((plies, tmove, child))
+        }).min_by_key(|(plies, _, _)| *plies)?;
+        Some(vec![(fastest.1, Source::Tablebase(fastest.2))])
+    }
+
+    /// The winning strategy, if the last search proved the win. Positions that occur in several
+    /// branches of the tree take the strategy of the first branch, the tree is walked depth first so
+    /// that no line of the strategy can run into a cycle.
+    pub fn proof_tree(&self) -> Option<ProofTree> {
+        if self.root_numbers()?.0 != 0 { return None; }
+        let mut proof = ProofTree::new(self.nodes[0].board);
+        let mut stack = vec![vec![Source::Tree(0)]];
+        while let Some(pending) = stack.last_mut() {
+            let source = match pending.pop() {
+                Some(source) => source,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            let board = self.source_board(&source);
+            if board.get_winner().is_some() || proof.contains(&board) { continue; }
+            let moves = self.proof_moves(&source)?;
+            match board.get_player() == self.prover {
+                true => { proof.strategy.insert(board.board, moves.first()?.0); }
+                false => { proof.replies.insert(board.board, moves.iter().map(|(tmove, _)| *tmove).collect()); }
+            }
+            stack.push(moves.into_iter().map(|(_, source)| source).collect());
+        }
+        Some(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::index::{unrank, Signature};
+    use super::super::solver::Solver;
+    use super::super::testing::{BENCHMARK};
+
+    #[test]
+    fn test_prove_benchmark() {
+        let board = BENCHMARK;
+        let mut search = ProofSearch::new(1_000_000);
+        assert_eq!(search.search(&board), Outcome::Proven);
+        let tmove = search.best_move().unwrap();
+        let reply = Solver::new(12, None).solve(&board.make_move(&tmove));
+        assert!(matches!(reply.value, Some(Value::Loss(_))));
+    }
+
+    #[test]
+    fn test_outcomes_agree_with_solver() {
+        let signature = Signature { attackers: 3, defenders: 1 };
+        let mut search = ProofSearch::new(1_000_000);
+        for index in (0..signature.size()).step_by(20011) {
+            let board = unrank(signature, index);
+            let outcome = search.search(&board);
+            let value = Solver::new(12, None).solve(&board).value.unwrap();
+            let expected = match value {
+                Value::Win(_) => Outcome::Proven,
+                _ => Outcome::Disproven
+            };
+            assert_eq!(outcome, expected, "{:#b}", board.board);
+        }
+    }
+}
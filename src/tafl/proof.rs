@@ -0,0 +1,207 @@
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use metrohash::MetroHashMap;
+use super::board::Board;
+use super::tmove::TMove;
+
+const HEADER: &str = "simpletafl proof 1";
+
+/// Winning strategy of the prover from a root position: one move for every position where the prover
+/// is to move, and all replies for every position where the opponent is. Decided positions have no
+/// entry.
+pub struct ProofTree {
+    pub root: Board,
+    pub prover: u8,
+    pub strategy: MetroHashMap<u64, TMove>,
+    pub replies: MetroHashMap<u64, Vec<TMove>>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_board(bits: &str) -> io::Result<Board> {
+    let digits = bits.strip_prefix("0x").ok_or_else(|| invalid(format!("board {bits} is not hex")))?;
+    u64::from_str_radix(digits, 16).map(|board| Board { board }).map_err(|_| invalid(format!("board {bits} is not hex")))
+}
+
+fn parse_move(tmove: &str) -> io::Result<TMove> {
+    TMove::from_str(tmove).map_err(|_| invalid(format!("move {tmove} does not parse")))
+}
+
+impl ProofTree {
+    pub fn new(root: Board) -> ProofTree {
+        ProofTree { root, prover: root.get_player(), strategy: MetroHashMap::default(), replies: MetroHashMap::default() }
+    }
+
+    pub fn contains(&self, board: &Board) -> bool {
+        self.strategy.contains_key(&board.board) || self.replies.contains_key(&board.board)
+    }
+
+    /// Number of positions with an entry.
+    pub fn len(&self) -> usize {
+        self.strategy.len() + self.replies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the proof as text: a header, the prover and the root, then one line per position, `P`
+    /// with the prover's move or `O` with the opponent's replies, boards in hex.
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{HEADER}")?;
+        writeln!(out, "prover {}", self.prover)?;
+        writeln!(out, "root {:#x}", self.root.board)?;
+        let mut strategy = self.strategy.iter().collect::<Vec<_>>();
+        strategy.sort_by_key(|(board, _)| **board);
+        for (board, tmove) in strategy {
+            writeln!(out, "P {board:#x} {tmove}")?;
+        }
+        let mut replies = self.replies.iter().collect::<Vec<_>>();
+        replies.sort_by_key(|(board, _)| **board);
+        for (board, tmoves) in replies {
+            let tmoves = tmoves.iter().map(|tmove| tmove.to_string()).collect::<Vec<String>>();
+            writeln!(out, "O {board:#x} {}", tmoves.join(" "))?;
+        }
+        out.flush()
+    }
+
+    pub fn read<R: BufRead>(input: R) -> io::Result<ProofTree> {
+        let mut lines = input.lines();
+        let mut next_line = || lines.next().unwrap_or_else(|| Err(invalid("proof ends early".to_string())));
+        if next_line()? != HEADER { return Err(invalid("not a proof file".to_string())); }
+        let prover = match next_line()?.strip_prefix("prover ") {
+            Some("0") => 0,
+            Some("1") => 1,
+            _ => { return Err(invalid("no prover".to_string())); }
+        };
+        let root = match next_line()?.strip_prefix("root ") {
+            Some(bits) => parse_board(bits)?,
+            None => { return Err(invalid("no root".to_string())); }
+        };
+
+        let mut proof = ProofTree { root, prover, strategy: MetroHashMap::default(), replies: MetroHashMap::default() };
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            let (kind, board) = match (fields.next(), fields.next()) {
+                (Some(kind), Some(board)) => (kind, parse_board(board)?),
+                (None, _) => { continue; }
+                _ => { return Err(invalid(format!("line {line} has no board"))); }
+            };
+            let tmoves = fields.map(parse_move).collect::<io::Result<Vec<TMove>>>()?;
+            match (kind, tmoves.as_slice()) {
+                ("P", [tmove]) => { proof.strategy.insert(board.board, *tmove); }
+                ("O", _) => { proof.replies.insert(board.board, tmoves); }
+                _ => { return Err(invalid(format!("line {line} is neither a move nor replies"))); }
+            }
+        }
+        Ok(proof)
+    }
+
+    /// Checks that the strategy wins from the root, relying on nothing but the rules: every line
+    /// following it ends with a win of the prover, a position decided for the prover or one where
+    /// the opponent cannot move, and never repeats a position. The listed replies have to be exactly
+    /// the opponent's moves. Returns the number of positions checked.
+    pub fn check(&self) -> Result<usize, String> {
+        // 1 while the position is on the current line, 2 once everything below it is checked
+        let mut state: MetroHashMap<u64, u8> = MetroHashMap::default();
+        let mut stack: Vec<(Board, Vec<Board>)> = Vec::new();
+        let enter = |board: Board, state: &mut MetroHashMap<u64, u8>| -> Result<Option<(Board, Vec<Board>)>, String> {
+            match state.get(&board.board) {
+                Some(1) => { return Err(format!("{:#x} repeats on a line of the strategy", board.board)); }
+                Some(_) => { return Ok(None); }
+                None => {}
+            }
+            match board.get_winner() {
+                Some(winner) if winner == self.prover => {
+                    state.insert(board.board, 2);
+                    return Ok(None);
+                }
+                Some(_) => { return Err(format!("{:#x} is lost for the prover", board.board)); }
+                None => {}
+            }
+            let possible = board.get_possible_moves();
+            let children = match board.get_player() == self.prover {
+                true => {
+                    let tmove = self.strategy.get(&board.board).ok_or_else(|| format!("{:#x} has no move", board.board))?;
+                    if !possible.contains(tmove) { return Err(format!("{tmove} is not possible in {:#x}", board.board)); }
+                    vec![board.make_move(tmove)]
+                }
+                false => {
+                    let replies = self.replies.get(&board.board).ok_or_else(|| format!("{:#x} has no replies", board.board))?;
+                    let complete = replies.len() == possible.len() && possible.iter().all(|tmove| replies.contains(tmove));
+                    if !complete { return Err(format!("the replies of {:#x} are not the possible moves", board.board)); }
+                    possible.iter().map(|tmove| board.make_move(tmove)).collect()
+                }
+            };
+            state.insert(board.board, 1);
+            Ok(Some((board, children)))
+        };
+
+        if let Some(frame) = enter(self.root, &mut state)? { stack.push(frame); }
+        while let Some((_, children)) = stack.last_mut() {
+            match children.pop() {
+                Some(child) => {
+                    if let Some(frame) = enter(child, &mut state)? { stack.push(frame); }
+                }
+                None => {
+                    let (board, _) = stack.pop().unwrap();
+                    state.insert(board.board, 2);
+                }
+            }
+        }
+        Ok(state.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+    use super::super::index::{unrank, Signature};
+    use super::super::pns::{Outcome, ProofSearch};
+    use super::super::tablebase::Tablebase;
+    use super::super::testing::{BENCHMARK};
+
+    #[test]
+    fn test_export_and_check() {
+        let board = BENCHMARK;
+        let mut search = ProofSearch::new(1_000_000);
+        assert_eq!(search.search(&board), Outcome::Proven);
+        let mut written = Vec::new();
+        search.proof_tree().unwrap().write(&mut written).unwrap();
+        let proof = ProofTree::read(written.as_slice()).unwrap();
+        assert!(proof.check().unwrap() > 1);
+
+        // every reply of the opponent has to be answered
+        let mut missing_reply = ProofTree::read(written.as_slice()).unwrap();
+        let (board, replies) = missing_reply.replies.iter_mut().find(|(_, replies)| replies.len() > 1).unwrap();
+        let board = *board;
+        replies.pop();
+        assert!(missing_reply.check().unwrap_err().contains(&format!("{board:#x}")));
+
+        let mut missing_move = ProofTree::read(written.as_slice()).unwrap();
+        missing_move.strategy.remove(&proof.root.board);
+        assert!(missing_move.check().is_err());
+    }
+
+    #[test]
+    fn test_proofs_through_tablebase() {
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(3, 0, |_, _| {});
+        let mut search = ProofSearch::new(100_000);
+        search.tablebase = Some(Arc::new(tablebase));
+        let signature = Signature { attackers: 3, defenders: 0 };
+        let mut proven = 0;
+        for index in (0..signature.size()).step_by(1009) {
+            let board = unrank(signature, index);
+            if board.get_winner().is_some() || search.search(&board) != Outcome::Proven { continue; }
+            let proof = search.proof_tree().unwrap();
+            assert_eq!(proof.check().map(|checked| checked > 0), Ok(true), "{:#b}", board.board);
+            proven += 1;
+        }
+        assert!(proven > 0);
+    }
+}
@@ -0,0 +1,158 @@
+use super::board::Board;
+use super::tmove::TMove;
+
+const DIRECTIONS: [(i8, i8); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+#[inline]
+fn bit(x: u8, y: u8) -> u32 {
+    1 << (24 - (5 * y + x))
+}
+
+fn step(pos: (u8, u8), direction: (i8, i8)) -> Option<(u8, u8)> {
+    let (x, y) = (pos.0 as i8 + direction.0, pos.1 as i8 + direction.1);
+    match (0..5).contains(&x) && (0..5).contains(&y) {
+        true => Some((x as u8, y as u8)),
+        false => None
+    }
+}
+
+/// A move that leads into a position, made in `previous` by the side that is not to move anymore.
+#[derive(Clone, Copy)]
+pub struct Unmove {
+    pub tmove: TMove,
+    /// Squares of the pieces the move captured, laid out like `Board::occupied`.
+    pub captured: u32,
+    pub previous: Board,
+}
+
+/// Pieces of the side to move that could be put back on the `candidates`, one entry per choice of
+/// every candidate: 0 for an empty square, otherwise the piece.
+fn uncaptures(board: &Board, candidates: &[(u8, u8)]) -> Vec<Vec<u8>> {
+    let pieces: &[u8] = match (board.get_player(), board.king()) {
+        (1, _) => &[1],
+        (_, 0) => &[2, 3],
+        _ => &[2]
+    };
+    let mut choices = vec![Vec::new()];
+    for _ in candidates {
+        choices = choices.into_iter().flat_map(|choice: Vec<u8>| {
+            std::iter::once(0).chain(pieces.iter().copied()).map(move |piece| {
+                let mut extended = choice.clone();
+                extended.push(piece);
+                extended
+            })
+        }).collect();
+    }
+    // the king is captured at most once
+    choices.retain(|choice| choice.iter().filter(|piece| **piece == 3).count() <= 1);
+    choices
+}
+
+/// Every move that could have led into `board`, including those that captured pieces, with the
+/// position before it. The side that moved is the one not to move in `board`. Captured pieces are put
+/// back next to the destination where the moved piece and a partner beyond enclosed them, a captured
+/// king only if `board` has none. Previous positions that were decided already are left out, the game
+/// would have ended there.
+pub fn unmoves(board: &Board) -> Vec<Unmove> {
+    let mover = 1 - board.get_player();
+    let occupied = board.occupied();
+    let (attackers, defenders, king) = (board.attackers(), board.defenders() & !board.king(), board.king());
+    let mut unmoves = Vec::new();
+
+    for ey in 0..5 {
+        for ex in 0..5 {
+            let piece = board.get(ex, ey);
+            let moved = match (mover, piece) {
+                (1, 1) | (0, 2) | (0, 3) => bit(ex, ey),
+                _ => { continue; }
+            };
+            for direction in DIRECTIONS {
+                let mut start = step((ex, ey), direction);
+                while let Some((sx, sy)) = start {
+                    if occupied & bit(sx, sy) != 0 { break; }
+                    let origin = bit(sx, sy);
+                    let back = |bits: u32| match bits & moved {
+                        0 => bits,
+                        _ => (bits & !moved) | origin
+                    };
+                    let tmove = TMove { start: (sx, sy), end: (ex, ey) };
+
+                    // empty neighbours with a piece beyond could have held a captured piece
+                    let candidates = DIRECTIONS.iter().filter_map(|direction| {
+                        let middle = step((ex, ey), *direction)?;
+                        let outer = step(middle, *direction)?;
+                        let free = middle != (sx, sy) && occupied & bit(middle.0, middle.1) == 0;
+                        let partner = occupied & bit(outer.0, outer.1) != 0;
+                        Some(middle).filter(|_| free && partner)
+                    }).collect::<Vec<(u8, u8)>>();
+
+                    for choice in uncaptures(board, &candidates) {
+                        let (mut attackers, mut defenders, mut king) = (back(attackers), back(defenders), back(king));
+                        let mut captured = 0;
+                        for (&(x, y), &piece) in candidates.iter().zip(&choice) {
+                            match piece {
+                                1 => attackers |= bit(x, y),
+                                2 => defenders |= bit(x, y),
+                                3 => king |= bit(x, y),
+                                _ => { continue; }
+                            }
+                            captured |= bit(x, y);
+                        }
+                        let previous = Board::from_bitboards(mover, attackers, defenders, king);
+                        // a piece put back that the move would not capture is no predecessor
+                        if previous.get_winner().is_none() && previous.make_move(&tmove).board == board.board {
+                            unmoves.push(Unmove { tmove, captured, previous });
+                        }
+                    }
+                    start = step((sx, sy), direction);
+                }
+            }
+        }
+    }
+    unmoves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{Lcg, START};
+    use super::super::index::{unrank, Signature};
+
+    #[test]
+    fn test_unmoves_undo_played_moves() {
+        let mut random = Lcg(3);
+        let mut captures = 0;
+        for _ in 0..20 {
+            let mut board = START;
+            while board.get_winner().is_none() && !board.moves().is_empty() {
+                let tmove = random.pick(&board.moves());
+                let next = board.make_move(&tmove);
+                let unmoves = unmoves(&next);
+                assert!(unmoves.iter().any(|unmove| unmove.tmove == tmove && unmove.previous.board == board.board));
+                for unmove in &unmoves {
+                    assert_eq!(unmove.previous.make_move(&unmove.tmove).board, next.board);
+                    assert_eq!(unmove.captured, unmove.previous.occupied() & !next.occupied() & !bit(unmove.tmove.start.0, unmove.tmove.start.1));
+                }
+                captures += board.is_capture(&tmove) as u32;
+                board = next;
+            }
+        }
+        assert!(captures > 0);
+    }
+
+    #[test]
+    fn test_unmoves_are_complete() {
+        // every child of a sample of positions with little material lists its parent
+        for signature in [Signature { attackers: 2, defenders: 1 }, Signature { attackers: 3, defenders: 0 }] {
+            for index in (0..signature.size()).step_by(101) {
+                let board = unrank(signature, index);
+                if board.get_winner().is_some() { continue; }
+                for tmove in board.moves().iter() {
+                    let child = board.make_move(tmove);
+                    let found = unmoves(&child).iter().filter(|unmove| unmove.tmove == *tmove && unmove.previous.board == board.board).count();
+                    assert_eq!(found, 1);
+                }
+            }
+        }
+    }
+}
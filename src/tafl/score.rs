@@ -0,0 +1,72 @@
+/// Bound of all scores, used as the initial search window.
+pub const INFINITY: i32 = 10_000;
+/// Score of a game won at the root. A game won `ply` plies after the root scores `WIN - ply`.
+pub const WIN: i32 = 9_000;
+/// Longest distance to the end of a game that decided scores can express.
+pub const MAX_WIN_PLY: i32 = 1_000;
+/// Heuristic scores lie within `-MAX_EVAL..=MAX_EVAL`, all decided scores outside of it.
+pub const MAX_EVAL: i32 = WIN - MAX_WIN_PLY;
+
+/// Score of a game that the side to move at the root wins `ply` plies after the root.
+pub fn win_in(ply: i32) -> i32 {
+    WIN - ply
+}
+
+/// Score of a game that the side to move at the root loses `ply` plies after the root.
+pub fn loss_in(ply: i32) -> i32 {
+    -WIN + ply
+}
+
+pub fn is_decided(score: i32) -> bool {
+    score.abs() > MAX_EVAL
+}
+
+/// Number of own moves the side to move needs to win, if `score` is a won score.
+pub fn moves_to_win(score: i32) -> Option<i32> {
+    match score > MAX_EVAL {
+        true => Some((WIN - score + 1) / 2),
+        false => None
+    }
+}
+
+/// Number of moves the opponent needs to win, if `score` is a lost score.
+pub fn moves_to_loss(score: i32) -> Option<i32> {
+    moves_to_win(-score)
+}
+
+fn side(player: u8) -> &'static str {
+    match player {
+        0 => "defenders",
+        _ => "attackers"
+    }
+}
+
+/// Describes a score from the point of view of `player`, the side to move, as "defenders win in 7"
+/// for decided games and as the plain number otherwise.
+pub fn describe(score: i32, player: u8) -> String {
+    match (moves_to_win(score), moves_to_loss(score)) {
+        (Some(moves), _) => format!("{} win in {moves}", side(player)),
+        (_, Some(moves)) => format!("{} win in {moves}", side(1 - player)),
+        _ => score.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        // the side to move wins with its first move, the terminal position is one ply deep
+        assert_eq!(moves_to_win(win_in(1)), Some(1));
+        assert_eq!(moves_to_win(win_in(13)), Some(7));
+        // the opponent ends the game with its fourth move
+        assert_eq!(moves_to_loss(loss_in(8)), Some(4));
+        assert_eq!(moves_to_win(MAX_EVAL), None);
+
+        assert_eq!(describe(win_in(13), 0), "defenders win in 7");
+        assert_eq!(describe(loss_in(8), 0), "attackers win in 4");
+        assert_eq!(describe(loss_in(8), 1), "defenders win in 4");
+        assert_eq!(describe(-35, 1), "-35");
+    }
+}
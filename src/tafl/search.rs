@@ -0,0 +1,302 @@
+use std::future::Future;
+use std::iter;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use futures::channel::oneshot;
+use futures::FutureExt;
+use super::board::Board;
+use super::clock::TimeBudget;
+use super::negamax::Negamax;
+use super::score::{moves_to_win, INFINITY};
+use super::stats::SearchStats;
+use super::tmove::TMove;
+
+/// Result of one completed iteration of iterative deepening.
+#[derive(Clone, Debug)]
+pub struct DepthResult {
+    pub depth: i32,
+    pub score: i32,
+    pub tmove: Option<TMove>,
+    pub pv: Vec<TMove>,
+    pub elapsed: f32,
+    /// False if the iteration was interrupted and only some of the root moves were searched.
+    pub complete: bool,
+    /// Counters of this iteration alone.
+    pub stats: SearchStats,
+    /// Node growth per ply compared to the previous iteration.
+    pub branching_factor: Option<f64>,
+    /// Score, move and principal variation of the best root moves, best first, if more than one
+    /// line was asked for.
+    pub lines: Vec<(i32, TMove, Vec<TMove>)>,
+}
+
+/// Limits of a search. Iterative deepening starts at `start_depth` and continues with every
+/// `step`-th depth up to `max_depth` unless another limit is hit first.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    pub start_depth: i32,
+    pub max_depth: i32,
+    pub step: usize,
+    pub time: Option<TimeBudget>,
+    /// Approximate number of nodes, checked together with the clock.
+    pub nodes: Option<u64>,
+    /// Stop as soon as a win for the side to move in at most this many of its moves is found.
+    pub win_in: Option<i32>,
+    /// Only consider these moves at the root.
+    pub root_moves: Option<Vec<TMove>>,
+    /// Number of best root moves to search lines for.
+    pub multipv: usize,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits { start_depth: 1, max_depth: 20, step: 2, time: None, nodes: None, win_in: None, root_moves: None, multipv: 1 }
+    }
+}
+
+impl SearchLimits {
+    fn is_satisfied(&self, result: &DepthResult, elapsed: Duration) -> bool {
+        let soft_time = self.time.is_some_and(|time| elapsed >= time.soft);
+        let won = match (self.win_in, moves_to_win(result.score)) {
+            (Some(n), Some(moves)) => moves <= n,
+            _ => false
+        };
+        soft_time || won
+    }
+
+    fn depths(&self) -> impl Iterator<Item = i32> {
+        // a win in n moves lies at most 2n plies deep
+        let max_depth = match self.win_in {
+            Some(n) => self.max_depth.min(2 * n),
+            None => self.max_depth
+        };
+        iter::once(self.start_depth.min(max_depth)).chain((self.start_depth + 1..=max_depth).step_by(self.step))
+    }
+}
+
+/// The principal variation of a depth `d` search that found `tmove`. It is followed by the moves the
+/// table holds after it, since later multi-PV lines and helper threads overwrite the root entry.
+fn line(negamax: &Negamax, board: &Board, tmove: TMove, d: i32) -> Vec<TMove> {
+    let mut pv = vec![tmove];
+    pv.extend(negamax.principal_variation(&board.make_move(&tmove), d as usize));
+    pv
+}
+
+/// Searches `board` by iterative deepening within `limits`, calling `on_depth` after each
+/// iteration. Stops early when the search is stopped, either from another thread, by its deadline
+/// or node budget, or from within `on_depth`. An interrupted iteration is only used if at least
+/// one root move was searched to the end, since that move is at least as good as the one it was
+/// compared against. Root moves that leave nothing to search give no result.
+pub fn iterative_deepening<F>(negamax: &mut Negamax, board: &Board, limits: &SearchLimits, pvs: bool, mut on_depth: F) -> Option<DepthResult>
+where F: FnMut(&Negamax, &DepthResult) {
+    if let Some(root_moves) = &limits.root_moves {
+        if !board.moves().iter().any(|tmove| root_moves.contains(tmove)) { return None; }
+    }
+    let t0 = Instant::now();
+    negamax.reset_stats();
+    negamax.set_limits(limits.time.map(|time| t0 + time.hard), limits.nodes);
+    negamax.root_moves = limits.root_moves.clone();
+
+    let mut best: Option<DepthResult> = None;
+    let mut snapshot = negamax.stats;
+    for d in limits.depths() {
+        let t1 = Instant::now();
+        let previous = best.as_ref().map(|last| (last.depth, last.stats));
+        let iteration_stats = |negamax: &Negamax| {
+            let stats = negamax.stats - snapshot;
+            let branching_factor = previous.and_then(|(depth, last)| stats.effective_branching_factor(&last, d - depth));
+            (stats, branching_factor)
+        };
+        let mut lines = Vec::new();
+        let (score, tmove) = match &best {
+            _ if limits.multipv > 1 => {
+                lines = negamax.solve_multipv(board, d, pvs, limits.multipv);
+                lines.first().map_or((-INFINITY, None), |(score, tmove, _)| (*score, Some(*tmove)))
+            }
+            Some(last) => negamax.solve_aspiration(board, d, pvs, last.score),
+            None => negamax.solve(board, d, pvs)
+        };
+        if negamax.is_stopped() {
+            // an interrupted multi-PV iteration keeps the lines it completed
+            let partial = match (lines.first(), negamax.root_best) {
+                (Some((score, tmove, pv)), _) => Some((*score, *tmove, pv.clone())),
+                (None, Some((score, tmove))) => Some((score, tmove, line(negamax, board, tmove, d))),
+                (None, None) => None
+            };
+            if let Some((score, tmove, pv)) = partial {
+                let (stats, branching_factor) = iteration_stats(negamax);
+                let result = DepthResult { depth: d, score, tmove: Some(tmove), pv, elapsed: t1.elapsed().as_secs_f32(), complete: false, stats, branching_factor, lines };
+                on_depth(negamax, &result);
+                best = Some(result);
+            }
+            break;
+        }
+
+        let (stats, branching_factor) = iteration_stats(negamax);
+        snapshot = negamax.stats;
+        let pv = match (lines.first(), tmove) {
+            (Some((_, _, pv)), _) => pv.clone(),
+            (None, Some(tmove)) => line(negamax, board, tmove, d),
+            (None, None) => Vec::new()
+        };
+        let result = DepthResult { depth: d, score, tmove, pv, elapsed: t1.elapsed().as_secs_f32(), complete: true, stats, branching_factor, lines };
+        on_depth(negamax, &result);
+        let satisfied = limits.is_satisfied(&result, t0.elapsed());
+        best = Some(result);
+        if satisfied || negamax.is_stopped() { break; }
+    }
+
+    negamax.set_limits(None, None);
+    negamax.root_moves = None;
+    best
+}
+
+pub struct SearchOutcome {
+    /// The search is handed back so its transposition table can be reused for the next move.
+    pub negamax: Negamax,
+    pub result: Option<DepthResult>,
+}
+
+/// Handle to a search running on a background thread. Awaiting it yields the result of the
+/// deepest completed iteration.
+pub struct SearchHandle {
+    stop: Arc<AtomicBool>,
+    best: Arc<Mutex<Option<DepthResult>>>,
+    receiver: oneshot::Receiver<SearchOutcome>,
+}
+
+impl SearchHandle {
+    /// Interrupts the search, the handle then resolves with the last completed iteration.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn best_so_far(&self) -> Option<DepthResult> {
+        self.best.lock().unwrap().clone()
+    }
+}
+
+impl Future for SearchHandle {
+    type Output = SearchOutcome;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<SearchOutcome> {
+        match self.receiver.poll_unpin(cx) {
+            Poll::Ready(Ok(outcome)) => Poll::Ready(outcome),
+            Poll::Ready(Err(_)) => panic!("search thread ended without a result"),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+/// Runs `iterative_deepening` on its own thread and returns immediately. `on_depth` is called on
+/// the search thread after every completed iteration.
+pub fn spawn_search<F>(mut negamax: Negamax, board: Board, limits: SearchLimits, pvs: bool, mut on_depth: F) -> SearchHandle
+where F: FnMut(&Negamax, &DepthResult) + Send + 'static {
+    let (sender, receiver) = oneshot::channel();
+    let best = Arc::new(Mutex::new(None));
+    negamax.clear_stop();
+    let handle = SearchHandle { stop: negamax.stop_flag(), best: best.clone(), receiver };
+
+    thread::spawn(move || {
+        let result = iterative_deepening(&mut negamax, &board, &limits, pvs, |negamax, result| {
+            *best.lock().unwrap() = Some(result.clone());
+            on_depth(negamax, result);
+        });
+        // the receiver may have been dropped, nobody is interested in the result then
+        let _ = sender.send(SearchOutcome { negamax, result });
+    });
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use futures::executor::block_on;
+    use super::super::score::win_in;
+    use super::super::testing::{START};
+
+    #[test]
+    fn test_stop_background_search() {
+        let board = START;
+        let limits = SearchLimits { max_depth: 40, step: 1, ..SearchLimits::default() };
+        let handle = spawn_search(Negamax::new(), board, limits, true, |_, _| {});
+        while handle.best_so_far().is_none() {
+            thread::yield_now();
+        }
+        handle.stop();
+        let outcome = block_on(handle);
+        let result = outcome.result.unwrap();
+        assert!(result.depth < 40);
+        assert!(board.get_possible_moves().contains(&result.tmove.unwrap()));
+    }
+
+    #[test]
+    fn test_root_moves() {
+        let board = START;
+        let allowed = TMove::from_str("b3-b4").unwrap();
+        let limits = SearchLimits { max_depth: 4, root_moves: Some(vec![allowed]), ..SearchLimits::default() };
+        let result = iterative_deepening(&mut Negamax::new(), &board, &limits, true, |_, _| {}).unwrap();
+        assert_eq!(result.tmove, Some(allowed));
+
+        // the attackers' move is not possible with the defenders to move
+        let limits = SearchLimits { root_moves: Some(vec![TMove::from_str("a1-a2").unwrap()]), ..limits };
+        assert!(iterative_deepening(&mut Negamax::new(), &board, &limits, true, |_, _| {}).is_none());
+    }
+
+    #[test]
+    fn test_multipv_keeps_root_moves() {
+        let board = START;
+        let allowed = ["b3-b4", "b3-b5", "c2-a2"].map(|tmove| TMove::from_str(tmove).unwrap()).to_vec();
+        let limits = SearchLimits { max_depth: 4, root_moves: Some(allowed.clone()), multipv: 5, ..SearchLimits::default() };
+        let mut negamax = Negamax::new();
+        let result = iterative_deepening(&mut negamax, &board, &limits, true, |_, _| {}).unwrap();
+        assert_eq!(result.lines.len(), allowed.len());
+        assert!(result.lines.iter().all(|(_, tmove, pv)| allowed.contains(tmove) && pv[0] == *tmove));
+        assert!(result.lines.windows(2).all(|pair| pair[0].0 >= pair[1].0));
+        assert_eq!(result.tmove, Some(result.lines[0].1));
+        assert!(negamax.root_moves.is_none());
+    }
+
+    #[test]
+    fn test_depths() {
+        let depths = |limits: SearchLimits| limits.depths().collect::<Vec<i32>>();
+        assert_eq!(depths(SearchLimits { max_depth: 8, ..SearchLimits::default() }), vec![1, 2, 4, 6, 8]);
+        assert_eq!(depths(SearchLimits { start_depth: 3, max_depth: 6, step: 1, ..SearchLimits::default() }), vec![3, 4, 5, 6]);
+        // a win in 3 moves needs no more than 6 plies
+        assert_eq!(depths(SearchLimits { max_depth: 20, win_in: Some(3), ..SearchLimits::default() }), vec![1, 2, 4, 6]);
+        assert_eq!(depths(SearchLimits { start_depth: 8, max_depth: 4, ..SearchLimits::default() }), vec![4]);
+    }
+
+    #[test]
+    fn test_is_satisfied() {
+        let result = |score: i32| DepthResult { depth: 5, score, tmove: None, pv: Vec::new(), elapsed: 0.0, complete: true,
+            stats: SearchStats::default(), branching_factor: None, lines: Vec::new() };
+        let second = Duration::from_secs(1);
+        let limits = SearchLimits { win_in: Some(3), ..SearchLimits::default() };
+        assert!(limits.is_satisfied(&result(win_in(5)), second));
+        assert!(!limits.is_satisfied(&result(win_in(7)), second));
+        assert!(!limits.is_satisfied(&result(-win_in(5)), second));
+        assert!(!SearchLimits::default().is_satisfied(&result(win_in(5)), second));
+
+        let limits = SearchLimits { time: Some(TimeBudget::fixed(2 * second)), ..SearchLimits::default() };
+        assert!(!limits.is_satisfied(&result(0), second / 2));
+        assert!(limits.is_satisfied(&result(0), second));
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let board = START;
+        let limits = SearchLimits { max_depth: 40, step: 1, nodes: Some(20_000), ..SearchLimits::default() };
+        let mut negamax = Negamax::new();
+        let result = iterative_deepening(&mut negamax, &board, &limits, true, |_, _| {}).unwrap();
+        assert!(result.depth < 40);
+        assert!(board.moves().contains(&result.tmove.unwrap()));
+        // the limit is only checked every few nodes
+        assert!(negamax.stats.nodes < 20_000 + 2 * 1024);
+    }
+}
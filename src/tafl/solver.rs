@@ -0,0 +1,384 @@
+use std::ops::RangeInclusive;
+use metrohash::{MetroHashMap, MetroHashSet};
+use super::board::Board;
+use super::negamax::Negamax;
+use super::score::{is_decided, WIN};
+use super::tmove::TMove;
+
+// deepest search for a win, deep enough for the killer table to cover quiescence on top of it
+const MAX_DEPTH: i32 = 48;
+
+/// Game-theoretic value of a position for its side to move. A position that repeats with the same
+/// side to move is a draw, so a side that cannot be forced to lose within a finite number of moves
+/// holds the draw. Wins and losses carry the number of plies to the end of the game, if known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Win(Option<u32>),
+    Loss(Option<u32>),
+    Draw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Solution {
+    /// `None` if the node budget ran out before the value was proven.
+    pub value: Option<Value>,
+    /// The fastest win, the slowest loss or a move that keeps the draw.
+    pub tmove: Option<TMove>,
+}
+
+fn pieces(board: &Board, side: u8) -> u32 {
+    match side {
+        0 => board.defenders(),
+        _ => board.attackers()
+    }
+}
+
+/// How a position stands in the proof that a side does not lose.
+enum Status {
+    /// The side does not lose, assuming the positions of the current search from the given index on
+    /// do not lose either.
+    Holds(u32),
+    Fails,
+    Explore,
+}
+
+/// Position on the path of the proof that a side does not lose.
+struct Frame {
+    key: u64,
+    index: u32,
+    lowlink: u32,
+    /// The side whose proof it is moves here and needs one move that holds, otherwise every move
+    /// of the opponent has to hold.
+    choosing: bool,
+    children: Vec<(TMove, Board)>,
+    next: usize,
+    scanned: bool,
+}
+
+/// Proves the value of positions. Short wins are found by searches over all moves with increasing
+/// depth. Draws are proven by finding, for each side, a set of positions that is closed under
+/// the opponent's moves, has a move within the set for every position of the side, and contains no
+/// lost position; following it the side either wins or the game repeats. Proven results are kept
+/// between calls.
+pub struct Solver {
+    /// Full width search without null moves and reductions, for the short wins.
+    negamax: Negamax,
+    /// Positions from which the side does not lose, by side.
+    holds: [MetroHashSet<u64>; 2],
+    /// Positions the side loses against best play, by side.
+    fails: [MetroHashSet<u64>; 2],
+    /// Plies up to which wins are searched for before the draw proofs are tried.
+    pub max_plies: u32,
+    pub node_limit: Option<u64>,
+    /// Nodes of both the searches and the draw proofs.
+    pub nodes: u64,
+    proof_nodes: u64,
+}
+
+impl Solver {
+    pub fn new(max_plies: u32, node_limit: Option<u64>) -> Solver {
+        Solver {
+            negamax: Negamax::new(),
+            holds: [MetroHashSet::default(), MetroHashSet::default()],
+            fails: [MetroHashSet::default(), MetroHashSet::default()],
+            max_plies,
+            node_limit,
+            nodes: 0,
+            proof_nodes: 0,
+        }
+    }
+
+    fn out_of_nodes(&self) -> bool {
+        self.node_limit.is_some_and(|limit| self.nodes >= limit)
+    }
+
+    /// Number of positions that are kept, including the transposition table of the search.
+    pub fn len(&self) -> usize {
+        self.negamax.map.len() + self.holds.iter().chain(&self.fails).map(|set| set.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Searches `board` with growing depths until its score is decided. The search has no forward
+    /// pruning, so a decided score holds against every defence. Once decided the search is repeated
+    /// at the depth of the game's end, which then contains every shorter line, making the distance
+    /// exact. Returns the distance in plies for the side to move, negative for a loss.
+    fn search_decided(&mut self, board: &Board, depths: RangeInclusive<i32>) -> Option<(i32, Option<TMove>)> {
+        let mut exact = false;
+        let mut depth = *depths.start();
+        while depth <= *depths.end() || exact {
+            let (score, tmove) = self.negamax.solve(board, depth, true);
+            self.nodes = self.negamax.stats.nodes + self.proof_nodes;
+            if self.negamax.is_stopped() { return None; }
+            if is_decided(score) {
+                let plies = WIN - score.abs();
+                if exact || plies <= depth { return Some((plies * score.signum(), tmove)); }
+                exact = true;
+                depth = plies;
+                continue;
+            }
+            depth += 1;
+        }
+        None
+    }
+
+    fn status(&self, board: &Board, side: u8, path: &MetroHashMap<u64, u32>, pending: &MetroHashMap<u64, u32>) -> Status {
+        let s = side as usize;
+        match board.get_winner() {
+            Some(winner) if winner == side => { return Status::Holds(u32::MAX); }
+            Some(_) => { return Status::Fails; }
+            None => {}
+        }
+        let key = board.board;
+        if self.holds[s].contains(&key) { return Status::Holds(u32::MAX); }
+        if self.fails[s].contains(&key) { return Status::Fails; }
+        // reaching a position of the current path again is a repetition
+        if let Some(&index) = path.get(&key) { return Status::Holds(index); }
+        if let Some(&lowlink) = pending.get(&key) { return Status::Holds(lowlink); }
+        Status::Explore
+    }
+
+    /// Search frame of `board`. `previous` are the pieces of `side` one move earlier.
+    fn frame(&mut self, board: &Board, side: u8, index: u32, previous: Option<u32>) -> Frame {
+        self.proof_nodes += 1;
+        self.nodes += 1;
+        let mut children = board.moves().iter().map(|tmove| (*tmove, board.make_move(tmove))).collect::<Vec<(TMove, Board)>>();
+        // moves that end the game first, then moves of the side back to where its pieces were, which
+        // keep the positions to prove few, then the positions that look worst for the side to move
+        // there, which are the most promising moves for both the side and its opponent
+        children.sort_by_cached_key(|(_, child)| (child.get_winner().is_none(), Some(pieces(child, side)) != previous, child.eval()));
+        Frame { key: board.board, index, lowlink: index, choosing: board.get_player() == side, children, next: 0, scanned: false }
+    }
+
+    /// Proves whether `side` can avoid losing from `board`, returning the move that keeps it safe if
+    /// `side` is to move. Positions proven to hold are only stored once they no longer depend on a
+    /// repetition of a position that is still being searched, positions that fail always are.
+    /// Returns `None` if the node budget ran out.
+    fn prove_holds(&mut self, board: &Board, side: u8) -> Option<(bool, Option<TMove>)> {
+        let s = side as usize;
+        let mut path = MetroHashMap::default();
+        // positions that hold as long as an earlier position of the path holds, in search order
+        let mut pending: Vec<(u64, u32)> = Vec::new();
+        let mut pending_lowlinks = MetroHashMap::default();
+        match self.status(board, side, &path, &pending_lowlinks) {
+            Status::Holds(_) => { return Some((true, None)); }
+            Status::Fails => { return Some((false, None)); }
+            Status::Explore => {}
+        }
+
+        let mut index = 0;
+        let mut stack = vec![self.frame(board, side, index, None)];
+        path.insert(board.board, index);
+        let mut root_move = None;
+        loop {
+            if self.out_of_nodes() { return None; }
+            let at_root = stack.len() == 1;
+            let frame = stack.last_mut().unwrap();
+            let mut result = None;
+            let mut explore = None;
+            if !frame.scanned {
+                // settle the position at once if a move repeats or reaches a known position
+                frame.scanned = true;
+                for &(tmove, child) in &frame.children {
+                    match (self.status(&child, side, &path, &pending_lowlinks), frame.choosing) {
+                        (Status::Holds(lowlink), true) => {
+                            frame.lowlink = frame.lowlink.min(lowlink);
+                            if at_root { root_move = Some(tmove); }
+                            result = Some(true);
+                            break;
+                        }
+                        (Status::Fails, false) => {
+                            result = Some(false);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            while result.is_none() && frame.next < frame.children.len() {
+                let (tmove, child) = frame.children[frame.next];
+                frame.next += 1;
+                let status = self.status(&child, side, &path, &pending_lowlinks);
+                match (status, frame.choosing) {
+                    (Status::Holds(lowlink), true) => {
+                        frame.lowlink = frame.lowlink.min(lowlink);
+                        if at_root { root_move = Some(tmove); }
+                        result = Some(true);
+                        break;
+                    }
+                    (Status::Holds(lowlink), false) => { frame.lowlink = frame.lowlink.min(lowlink); }
+                    (Status::Fails, true) => {}
+                    (Status::Fails, false) => {
+                        result = Some(false);
+                        break;
+                    }
+                    (Status::Explore, _) => {
+                        explore = Some(child);
+                        break;
+                    }
+                }
+            }
+
+            if let Some(child) = explore {
+                index += 1;
+                path.insert(child.board, index);
+                let previous = stack.len().checked_sub(2).map(|i| pieces(&Board { board: stack[i].key }, side));
+                let child_frame = self.frame(&child, side, index, previous);
+                stack.push(child_frame);
+                continue;
+            }
+
+            // without a move that holds the side loses, if every opponent move holds it does not
+            let holds = result.unwrap_or(!frame.choosing);
+            let frame = stack.pop().unwrap();
+            path.remove(&frame.key);
+            if holds && frame.lowlink < frame.index {
+                pending.push((frame.key, frame.index));
+                pending_lowlinks.insert(frame.key, frame.lowlink);
+            } else {
+                // every later pending position was found while searching this one, it holds with it
+                // or has to be searched again
+                while let Some(&(key, pending_index)) = pending.last() {
+                    if pending_index < frame.index { break; }
+                    pending.pop();
+                    pending_lowlinks.remove(&key);
+                    if holds { self.holds[s].insert(key); }
+                }
+                match holds {
+                    true => self.holds[s].insert(frame.key),
+                    false => self.fails[s].insert(frame.key)
+                };
+            }
+
+            match stack.last_mut() {
+                Some(parent) => {
+                    // the child is searched again through the status of its position
+                    parent.next -= 1;
+                }
+                None => { return Some((holds, root_move)); }
+            }
+        }
+    }
+
+    fn decided(distance: i32, tmove: Option<TMove>) -> Solution {
+        let plies = Some(distance.unsigned_abs());
+        match distance > 0 {
+            true => Solution { value: Some(Value::Win(plies)), tmove },
+            false => Solution { value: Some(Value::Loss(plies)), tmove }
+        }
+    }
+
+    /// Proves the value of `board` for its side to move.
+    pub fn solve(&mut self, board: &Board) -> Solution {
+        let unknown = Solution { value: None, tmove: None };
+        let player = board.get_player();
+        match board.get_winner() {
+            Some(winner) if winner == player => { return Solution { value: Some(Value::Win(Some(0))), tmove: None }; }
+            Some(_) => { return Solution { value: Some(Value::Loss(Some(0))), tmove: None }; }
+            None => {}
+        }
+        if board.moves().is_empty() {
+            return Solution { value: Some(Value::Loss(Some(0))), tmove: None };
+        }
+
+        self.negamax.clear_stop();
+        self.negamax.set_limits(None, self.node_limit);
+        match self.search_decided(board, 1..=self.max_plies as i32) {
+            Some((distance, tmove)) => { return Solver::decided(distance, tmove); }
+            None if self.out_of_nodes() => { return unknown; }
+            None => {}
+        }
+
+        let keeps_draw = match self.prove_holds(board, player) {
+            None => { return unknown; }
+            Some((false, _)) => { return self.solve_decided(board, false); }
+            Some((true, tmove)) => tmove
+        };
+        match self.prove_holds(board, 1 - player) {
+            None => unknown,
+            Some((false, _)) => self.solve_decided(board, true),
+            Some((true, _)) => Solution { value: Some(Value::Draw), tmove: keeps_draw }
+        }
+    }
+
+    /// Finds the distance of a position that is known to be won or lost by searching deeper. Without
+    /// the budget to do so the value is returned without distance.
+    fn solve_decided(&mut self, board: &Board, won: bool) -> Solution {
+        self.negamax.set_limits(None, self.node_limit.map(|limit| limit.saturating_sub(self.proof_nodes)));
+        if let Some((distance, tmove)) = self.search_decided(board, self.max_plies as i32 + 1..=MAX_DEPTH) {
+            return Solver::decided(distance, tmove);
+        }
+
+        let opponent = 1 - board.get_player() as usize;
+        let moves = board.moves();
+        match won {
+            // the opponent fails after the winning move
+            true => {
+                let tmove = moves.iter().find(|tmove| {
+                    let child = board.make_move(tmove);
+                    child.get_winner().is_some() || self.fails[opponent].contains(&child.board)
+                }).copied();
+                Solution { value: Some(Value::Win(None)), tmove }
+            }
+            false => Solution { value: Some(Value::Loss(None)), tmove: moves.first().copied() }
+        }
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::new(12, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::testing::{Lcg, BENCHMARK};
+
+    #[test]
+    fn test_prove_short_win() {
+        let board = BENCHMARK;
+        let mut solver = Solver::new(14, None);
+        let solution = solver.solve(&board);
+        assert_eq!(solution.value, Some(Value::Win(Some(13))));
+        assert_eq!(solution.tmove, "b2-d2".parse().ok());
+    }
+
+    #[test]
+    fn test_draw_proofs_agree_with_decided_values() {
+        // positions with few pieces from a fixed pseudo random sequence
+        let mut random = Lcg(99);
+        let mut decided = 0;
+        while decided < 20 {
+            let mut squares = [0u64; 25];
+            squares[random.below(25) as usize] = 3;
+            for (count, piece) in [(random.below(2), 2), (2 + random.below(3), 1)] {
+                let mut placed = 0;
+                while placed < count {
+                    let i = random.below(25) as usize;
+                    if squares[i] == 0 {
+                        squares[i] = piece;
+                        placed += 1;
+                    }
+                }
+            }
+            let board = Board { board: (random.below(2) << 50) | (0..25).map(|i| squares[i] << (48 - 2 * i)).sum::<u64>() };
+            if board.get_winner().is_some() { continue; }
+
+            let player = board.get_player();
+            let winner = match Solver::new(8, None).solve(&board).value {
+                Some(Value::Win(_)) => player,
+                Some(Value::Loss(_)) => 1 - player,
+                _ => { continue; }
+            };
+            // without the wins known, the proofs alone have to find out who cannot hold
+            let mut solver = Solver::new(0, None);
+            assert_eq!(solver.prove_holds(&board, winner).map(|(holds, _)| holds), Some(true), "{:#b}", board.board);
+            assert_eq!(solver.prove_holds(&board, 1 - winner).map(|(holds, _)| holds), Some(false), "{:#b}", board.board);
+            decided += 1;
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::ops::{AddAssign, Sub};
+
+/// Counters of a search. `Negamax` resets them at the start of every search, iterative deepening
+/// takes a snapshot after every iteration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Every visited node, including quiescence nodes.
+    pub nodes: u64,
+    pub normal_calls: u64,
+    pub transpo_calls: u64,
+    pub zero_window_calls: u64,
+    pub pvs_failed_calls: u64,
+    pub quiescence_calls: u64,
+    pub null_move_calls: u64,
+    pub reduced_calls: u64,
+    pub aspiration_researches: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub tt_stores: u64,
+    pub beta_cutoffs: u64,
+    /// Beta cutoffs caused by the first move searched in a node.
+    pub first_move_cutoffs: u64,
+    /// Nodes cut off because a shorter win was already known.
+    pub mate_distance_prunes: u64,
+    /// Nodes whose value was read from the tablebase.
+    pub tb_hits: u64,
+    /// Deepest ply reached, including quiescence search and reductions.
+    pub max_selective_depth: i32,
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    match b {
+        0 => 0.0,
+        _ => a as f64 / b as f64
+    }
+}
+
+impl SearchStats {
+    /// All searched children, the sum of the call counters.
+    pub fn calls(&self) -> u64 {
+        self.normal_calls + self.transpo_calls + self.zero_window_calls + self.pvs_failed_calls
+            + self.quiescence_calls + self.null_move_calls + self.reduced_calls
+    }
+
+    pub fn nodes_per_second(&self, seconds: f32) -> f64 {
+        match seconds > 0.0 {
+            true => self.nodes as f64 / seconds as f64,
+            false => 0.0
+        }
+    }
+
+    pub fn tt_hit_rate(&self) -> f64 {
+        ratio(self.tt_hits, self.tt_probes)
+    }
+
+    /// Stores per probe, low values mean most nodes are cut off before a best move is known.
+    pub fn tt_store_rate(&self) -> f64 {
+        ratio(self.tt_stores, self.tt_probes)
+    }
+
+    /// Share of cutoffs that happened on the first move, a measure of move ordering quality.
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        ratio(self.first_move_cutoffs, self.beta_cutoffs)
+    }
+
+    /// Growth of the node count per ply between an iteration and one `plies` shallower.
+    pub fn effective_branching_factor(&self, previous: &SearchStats, plies: i32) -> Option<f64> {
+        match (previous.nodes, plies) {
+            (0, _) | (_, 0) => None,
+            (nodes, _) => Some((self.nodes as f64 / nodes as f64).powf(1.0 / plies as f64))
+        }
+    }
+}
+
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: SearchStats) {
+        self.nodes += other.nodes;
+        self.normal_calls += other.normal_calls;
+        self.transpo_calls += other.transpo_calls;
+        self.zero_window_calls += other.zero_window_calls;
+        self.pvs_failed_calls += other.pvs_failed_calls;
+        self.quiescence_calls += other.quiescence_calls;
+        self.null_move_calls += other.null_move_calls;
+        self.reduced_calls += other.reduced_calls;
+        self.aspiration_researches += other.aspiration_researches;
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+        self.tt_stores += other.tt_stores;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.mate_distance_prunes += other.mate_distance_prunes;
+        self.tb_hits += other.tb_hits;
+        self.max_selective_depth = self.max_selective_depth.max(other.max_selective_depth);
+    }
+}
+
+/// Counters collected between two snapshots. The selective depth is the one of the later snapshot.
+impl Sub for SearchStats {
+    type Output = SearchStats;
+
+    fn sub(self, earlier: SearchStats) -> SearchStats {
+        SearchStats {
+            nodes: self.nodes - earlier.nodes,
+            normal_calls: self.normal_calls - earlier.normal_calls,
+            transpo_calls: self.transpo_calls - earlier.transpo_calls,
+            zero_window_calls: self.zero_window_calls - earlier.zero_window_calls,
+            pvs_failed_calls: self.pvs_failed_calls - earlier.pvs_failed_calls,
+            quiescence_calls: self.quiescence_calls - earlier.quiescence_calls,
+            null_move_calls: self.null_move_calls - earlier.null_move_calls,
+            reduced_calls: self.reduced_calls - earlier.reduced_calls,
+            aspiration_researches: self.aspiration_researches - earlier.aspiration_researches,
+            tt_probes: self.tt_probes - earlier.tt_probes,
+            tt_hits: self.tt_hits - earlier.tt_hits,
+            tt_stores: self.tt_stores - earlier.tt_stores,
+            beta_cutoffs: self.beta_cutoffs - earlier.beta_cutoffs,
+            first_move_cutoffs: self.first_move_cutoffs - earlier.first_move_cutoffs,
+            mate_distance_prunes: self.mate_distance_prunes - earlier.mate_distance_prunes,
+            tb_hits: self.tb_hits - earlier.tb_hits,
+            max_selective_depth: self.max_selective_depth,
+        }
+    }
+}
+
+/// Single line of `key=value` pairs, easy to grep and to parse.
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nodes={} normal={} transpo={} zero_window={} pvs_failed={} quiescence={} null_move={} reduced={} aspiration={} ",
+            self.nodes, self.normal_calls, self.transpo_calls, self.zero_window_calls, self.pvs_failed_calls,
+            self.quiescence_calls, self.null_move_calls, self.reduced_calls, self.aspiration_researches)?;
+        write!(f, "tt_probes={} tt_hits={} tt_stores={} cutoffs={} first_move_cutoffs={} mate_distance={} tb_hits={} seldepth={}",
+            self.tt_probes, self.tt_hits, self.tt_stores, self.beta_cutoffs, self.first_move_cutoffs, self.mate_distance_prunes,
+            self.tb_hits, self.max_selective_depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::negamax::Negamax;
+    use super::super::search::{iterative_deepening, SearchLimits};
+    use super::super::testing::{START};
+
+    #[test]
+    fn test_counters_add_up() {
+        let board = START;
+        let limits = SearchLimits { max_depth: 5, step: 1, ..SearchLimits::default() };
+        let mut negamax = Negamax::new();
+        let mut total = SearchStats::default();
+        iterative_deepening(&mut negamax, &board, &limits, true, |_, result| {
+            let stats = result.stats;
+            assert!(stats.nodes > 0);
+            assert!(stats.tt_hits <= stats.tt_probes);
+            assert!(stats.first_move_cutoffs <= stats.beta_cutoffs);
+            assert!(stats.max_selective_depth >= result.depth);
+            total += stats;
+        });
+        // the iterations together are the whole search
+        assert_eq!(total, negamax.stats);
+        assert_eq!(negamax.stats - total, SearchStats { max_selective_depth: total.max_selective_depth, ..SearchStats::default() });
+        assert!((0.0..=1.0).contains(&total.tt_hit_rate()));
+        assert!((0.0..=1.0).contains(&total.first_move_cutoff_rate()));
+        assert!(total.tt_store_rate() > 0.0);
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use metrohash::MetroHashMap;
+use super::board::Board;
+use super::index::{rank_symmetric, unrank_symmetric, Signature};
+use super::solver::Value;
+
+const MAGIC: &[u8; 8] = b"TAFLTB02";
+// entry of a position that was not decided, a draw under repetition once generation is complete
+const DRAW: u16 = 0;
+
+fn file_name(signature: Signature) -> String {
+    format!("tb_a{}_d{}.bin", signature.attackers, signature.defenders)
+}
+
+fn encode(value: Value) -> u16 {
+    match value {
+        Value::Win(Some(plies)) => (((plies + 1) << 1) | 1) as u16,
+        Value::Loss(Some(plies)) => ((plies + 1) << 1) as u16,
+        _ => DRAW
+    }
+}
+
+fn decode(entry: u16) -> Value {
+    let plies = Some((entry >> 1) as u32).filter(|_| entry != DRAW).map(|plies| plies - 1);
+    match (entry, entry & 1) {
+        (DRAW, _) => Value::Draw,
+        (_, 1) => Value::Win(plies),
+        _ => Value::Loss(plies)
+    }
+}
+
+/// Counts of a generated table.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TableStats {
+    pub positions: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+    /// Longest win or loss in plies.
+    pub longest: u32,
+    pub passes: u32,
+}
+
+/// Endgame tablebase with the value of every position of the generated material signatures, for the
+/// side to move, stored once per class of symmetric positions. Wins and losses come with the exact number of plies to the end of the game, positions
+/// that neither side can force are draws under repetition, like in the solver.
+pub struct Tablebase {
+    tables: MetroHashMap<Signature, Vec<u16>>,
+    max_pieces: u8,
+}
+
+impl Tablebase {
+    pub fn new() -> Tablebase {
+        Tablebase { tables: MetroHashMap::default(), max_pieces: 0 }
+    }
+
+    pub fn signatures(&self) -> Vec<Signature> {
+        let mut signatures = self.tables.keys().copied().collect::<Vec<Signature>>();
+        signatures.sort();
+        signatures
+    }
+
+    fn insert(&mut self, signature: Signature, table: Vec<u16>) {
+        self.max_pieces = self.max_pieces.max(signature.attackers + signature.defenders);
+        self.tables.insert(signature, table);
+    }
+
+    /// Value of `board` for its side to move, if its material is in the tablebase.
+    pub fn probe(&self, board: &Board) -> Option<Value> {
+        let signature = Signature::of(board)?;
+        if signature.attackers + signature.defenders > self.max_pieces { return None; }
+        let table = self.tables.get(&signature)?;
+        Some(decode(table[rank_symmetric(board) as usize]))
+    }
+
+    /// Value of a position reached in a table that is being generated, `None` while undecided.
+    fn lookup(&self, board: &Board, signature: Signature, current: &[u16]) -> Option<Value> {
+        if let Some(winner) = board.get_winner() {
+            return Some(if winner == board.get_player() { Value::Win(Some(0)) } else { Value::Loss(Some(0)) });
+        }
+        match Signature::of(board) {
+            Some(child) if child == signature => Some(current[rank_symmetric(board) as usize]).filter(|entry| *entry != DRAW).map(decode),
+            _ => self.probe(board)
+        }
+    }
+
+    /// Computes the table of `signature` by retrograde analysis. Every table with less material has
+    /// to be in the tablebase already, since captures lead there. Starting from the decided positions,
+    /// pass n finds the positions won or lost in exactly n plies: a win needs a move into a position
+    /// lost in n - 1 plies, a loss needs every move to lead into a won position, the longest win taking
+    /// n - 1 plies. Positions still open once no pass finds anything new are draws.
+    pub fn generate_table(&mut self, signature: Signature) -> TableStats {
+        let size = signature.symmetric_size() as usize;
+        let mut table = vec![DRAW; size];
+        let mut open = Vec::new();
+        for (i, entry) in table.iter_mut().enumerate() {
+            let board = unrank_symmetric(signature, i as u64);
+            match board.get_winner() {
+                Some(winner) if winner == board.get_player() => { *entry = encode(Value::Win(Some(0))); }
+                Some(_) => { *entry = encode(Value::Loss(Some(0))); }
+                None if board.moves().is_empty() => { *entry = encode(Value::Loss(Some(0))); }
+                None => { open.push(i); }
+            }
+        }
+
+        // decided lines through captures are at most as long as the longest line of smaller tables
+        let longest_smaller = self.tables.values().flatten().filter(|entry| **entry != DRAW).map(|entry| (entry >> 1) as u32 - 1).max().unwrap_or(0);
+        let mut plies = 0;
+        loop {
+            plies += 1;
+            let mut changed = false;
+            open.retain(|&i| {
+                let board = unrank_symmetric(signature, i as u64);
+                let mut all_won = true;
+                let mut longest_win = 0;
+                let mut wins = false;
+                for tmove in board.moves().iter() {
+                    match self.lookup(&board.make_move(tmove), signature, &table) {
+                        Some(Value::Loss(Some(n))) if n + 1 == plies => {
+                            wins = true;
+                            break;
+                        }
+                        Some(Value::Win(Some(n))) => { longest_win = longest_win.max(n + 1); }
+                        _ => { all_won = false; }
+                    }
+                }
+                let value = match (wins, all_won && longest_win == plies) {
+                    (true, _) => Value::Win(Some(plies)),
+                    (false, true) => Value::Loss(Some(plies)),
+                    _ => { return true; }
+                };
+                table[i] = encode(value);
+                changed = true;
+                false
+            });
+            if !changed && plies > longest_smaller + 1 { break; }
+        }
+
+        let mut stats = TableStats { positions: size as u64, passes: plies, ..TableStats::default() };
+        for entry in &table {
+            match decode(*entry) {
+                Value::Win(Some(n)) => { stats.wins += 1; stats.longest = stats.longest.max(n); }
+                Value::Loss(Some(n)) => { stats.losses += 1; stats.longest = stats.longest.max(n); }
+                _ => { stats.draws += 1; }
+            }
+        }
+        self.insert(signature, table);
+        stats
+    }
+
+    /// Generates every table with at most `max_attackers` attackers and `max_defenders` defenders
+    /// besides the king, smaller material first. Tables that are already present are kept.
+    pub fn generate<F>(&mut self, max_attackers: u8, max_defenders: u8, mut on_table: F)
+    where F: FnMut(Signature, &TableStats) {
+        let mut signatures = (0..=max_attackers)
+            .flat_map(|attackers| (0..=max_defenders).map(move |defenders| Signature { attackers, defenders }))
+            .collect::<Vec<Signature>>();
+        signatures.sort_by_key(|signature| (signature.attackers + signature.defenders, signature.attackers));
+        for signature in signatures {
+            if self.tables.contains_key(&signature) { continue; }
+            let stats = self.generate_table(signature);
+            on_table(signature, &stats);
+        }
+    }
+
+    /// Writes one file per table to `dir`: a header with the material, then two bytes per position.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        for (signature, table) in &self.tables {
+            let mut file = BufWriter::new(File::create(dir.join(file_name(*signature)))?);
+            file.write_all(MAGIC)?;
+            file.write_all(&[signature.attackers, signature.defenders])?;
+            for entry in table {
+                file.write_all(&entry.to_le_bytes())?;
+            }
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reads every table file in `dir`.
+    pub fn load(dir: &Path) -> io::Result<Tablebase> {
+        let mut tablebase = Tablebase::new();
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            let is_table = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("tb_") && name.ends_with(".bin"));
+            if !is_table { continue; }
+
+            let mut bytes = Vec::new();
+            BufReader::new(File::open(&path)?).read_to_end(&mut bytes)?;
+            let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {message}", path.display()));
+            if bytes.len() < 10 || &bytes[..8] != MAGIC { return Err(invalid("not a tablebase file")); }
+            let signature = Signature { attackers: bytes[8], defenders: bytes[9] };
+            if (bytes.len() - 10) as u64 != 2 * signature.symmetric_size() { return Err(invalid("wrong size")); }
+            let table = bytes[10..].chunks_exact(2).map(|entry| u16::from_le_bytes([entry[0], entry[1]])).collect();
+            tablebase.insert(signature, table);
+        }
+        Ok(tablebase)
+    }
+}
+
+impl Default for Tablebase {
+    fn default() -> Self {
+        Tablebase::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::index::unrank;
+    use super::super::solver::Solver;
+
+    #[test]
+    fn test_tables_agree_with_solver() {
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(3, 0, |_, _| {});
+        let signature = Signature { attackers: 3, defenders: 0 };
+        let dir = std::env::temp_dir().join(format!("simpletafl_tb_{}", std::process::id()));
+        tablebase.save(&dir).unwrap();
+        let loaded = Tablebase::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.signatures(), tablebase.signatures());
+
+        for i in (0..signature.size()).step_by(997) {
+            let board = unrank(signature, i);
+            let value = loaded.probe(&board).unwrap();
+            assert_eq!(Some(value), tablebase.probe(&board));
+            if board.get_winner().is_some() { continue; }
+            assert_eq!(Solver::new(12, None).solve(&board).value, Some(value), "{:#b}", board.board);
+        }
+    }
+}
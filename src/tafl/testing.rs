@@ -0,0 +1,25 @@
+// helpers shared by the tests of several modules
+
+use super::board::Board;
+
+/// Deterministic pseudo random numbers, so that tests walking through random games or positions
+/// always see the same ones.
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    /// A number below `n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % n
+    }
+
+    /// One of `items`, which must not be empty.
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// The preset boards of the command line.
+pub const START: Board = Board { board: 0b0_0100010001_0000100000_0110111001_0000100000_0100010001 };
+pub const BENCHMARK: Board = Board { board: 0b0_0000010000_0100000001_0110101001_0111000010_0001000001 };
+pub const EIGHTEEN_MOVE: Board = Board { board: 0b0_0100010000_0000000100_0110101001_0111000010_0001000001 };
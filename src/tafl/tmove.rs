@@ -1,3 +1,5 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 
 
@@ -13,13 +15,13 @@ pub enum TMoveError {
     InvalidArgumentError
 }
 
-impl ToString for TMove {
-    fn to_string(&self) -> String {
+impl fmt::Display for TMove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (sx, sy) = self.start;
         let (ex, ey) = self.end;
         let letters = ["a", "b", "c", "d", "e"];
         let numbers = (1..=5).rev().collect::<Vec<i32>>();
-        format!("{}{}-{}{}", letters[sx as usize], numbers[sy as usize], letters[ex as usize], numbers[ey as usize])
+        write!(f, "{}{}-{}{}", letters[sx as usize], numbers[sy as usize], letters[ex as usize], numbers[ey as usize])
     }
 }
 
@@ -62,9 +64,62 @@ impl FromStr for TMove {
 
         match (start, end) {
             ((Some(x_start), Some(y_start)), (Some(x_end), Some(y_end))) => { Ok(TMove { start: (x_start, y_start), end: (x_end, y_end) }) },
-            _ => { return Err(TMoveError::InvalidArgumentError); }
+            _ => Err(TMoveError::InvalidArgumentError)
         }
     }
 
     type Err = TMoveError;
-}
\ No newline at end of file
+}
+
+/// Upper bound on the number of moves of one side, 17 pieces with 8 free squares each.
+pub const MAX_MOVES: usize = 136;
+
+/// Fixed capacity list of moves that lives on the stack, so move generation does not allocate.
+#[derive(Clone, Copy)]
+pub struct MoveList {
+    moves: [TMove; MAX_MOVES],
+    len: usize
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        MoveList { moves: [TMove { start: (0, 0), end: (0, 0) }; MAX_MOVES], len: 0 }
+    }
+
+    #[inline]
+    pub fn push(&mut self, tmove: TMove) {
+        self.moves[self.len] = tmove;
+        self.len += 1;
+    }
+
+    pub fn retain<F: FnMut(&TMove) -> bool>(&mut self, mut keep: F) {
+        let mut len = 0;
+        for i in 0..self.len {
+            if keep(&self.moves[i]) {
+                self.moves[len] = self.moves[i];
+                len += 1;
+            }
+        }
+        self.len = len;
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        MoveList::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [TMove];
+
+    fn deref(&self) -> &[TMove] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [TMove] {
+        &mut self.moves[..self.len]
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Mutex;
+use metrohash::MetroHashMap;
+use super::tmove::TMove;
+
+const SHARDS: usize = 64;
+
+#[derive(Clone, Copy)]
+pub struct TTEntry {
+    pub tmove: TMove
+}
+
+/// Transposition table that can be shared between search threads. Positions are spread over
+/// independently locked shards, so threads rarely wait on each other.
+pub struct TranspositionTable {
+    shards: Vec<Mutex<MetroHashMap<u64, TTEntry>>>
+}
+
+impl TranspositionTable {
+    #[inline]
+    fn shard(&self, key: u64) -> &Mutex<MetroHashMap<u64, TTEntry>> {
+        // the board encoding keeps most information in the high bits, so mix before picking a shard
+        let i = (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 58) as usize % SHARDS;
+        &self.shards[i]
+    }
+
+    pub fn get(&self, key: u64) -> Option<TTEntry> {
+        self.shard(key).lock().unwrap().get(&key).copied()
+    }
+
+    pub fn insert(&self, key: u64, entry: TTEntry) {
+        self.shard(key).lock().unwrap().insert(key, entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn new() -> TranspositionTable {
+        TranspositionTable { shards: (0..SHARDS).map(|_| Mutex::new(MetroHashMap::default())).collect() }
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        TranspositionTable::new()
+    }
+}